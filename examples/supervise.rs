@@ -0,0 +1,49 @@
+use std::process::Command;
+
+use bastille::process::Stdio;
+use bastille::{Mapping, Sandbox};
+use tokio::task::JoinSet;
+
+const SANDBOXES: usize = 256;
+
+#[cfg(target_os = "linux")]
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .mount(Mapping::from_parts("/usr", "/usr", false).unwrap())
+        .soft_links(vec![
+            ("usr/bin", "/bin"),
+            ("usr/lib", "/lib"),
+            ("usr/lib64", "/lib64"),
+        ])
+        .stdout(Stdio::piped());
+
+    // Every child is awaited on the same thread, without a blocking `waitpid()` per sandbox.
+    let mut tasks = JoinSet::new();
+    for i in 0..SANDBOXES {
+        let child = sandbox
+            .spawn_async(
+                Command::new("sh")
+                    .env_clear()
+                    .env("PATH", "/usr/bin:/bin")
+                    .args(&["-c", &format!("sleep 1; echo {}", i)]),
+            )
+            .expect("Failed to spawn process in sandbox");
+
+        tasks.spawn(async move {
+            let output = child.wait_with_output().await.expect("Failed to wait");
+            assert!(output.status.success());
+            assert_eq!(output.stdout, format!("{}\n", i).as_bytes());
+        });
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        result.expect("Supervising task panicked");
+    }
+
+    println!("supervised {} sandboxes on one thread", SANDBOXES);
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {}
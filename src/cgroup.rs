@@ -0,0 +1,326 @@
+//! Cgroup v2 resource limits for sandboxed processes (Linux only).
+//!
+//! Every sandbox spawned with `Resources` gets a cgroup of its own. Unless another parent is given
+//! with `Resources::parent()`, it is created as a sibling of the cgroup of the calling process,
+//! i.e. beneath its parent. The calling process can't be the parent itself, since cgroup v2 doesn't
+//! allow enabling controllers for the children of a cgroup which holds processes, other than the
+//! root.
+//!
+//! The parent must be delegated to the calling user. This is the case for everything beneath the
+//! `user@.service` of systemd, so running in a scope of the user manager is enough, e.g. with
+//! `systemd-run --user --scope`. The parent must not hold any processes itself.
+//!
+//! The sandboxed process is moved into its cgroup before it starts setting up the sandbox, and the
+//! cgroup is removed once the process has been reaped, killing anything left behind in it.
+//!
+//! Like `std::process::Child`, dropping a `Child` doesn't kill the process. Its cgroup is still
+//! removed if the sandbox has exited by then, but is left behind if anything in it is running.
+
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use std::{mem, process};
+
+use libc::pid_t;
+
+use crate::Error;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const CGROUP2_SUPER_MAGIC: libc::c_long = 0x6367_7270;
+
+/// Distinguishes the cgroups of several sandboxes spawned by the same process.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Limits for the block I/O of a sandbox on a single device, as written to `io.max`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct IoLimit {
+    read_bps: Option<u64>,
+    write_bps: Option<u64>,
+    read_iops: Option<u64>,
+    write_iops: Option<u64>,
+}
+
+impl IoLimit {
+    pub fn new() -> Self {
+        IoLimit::default()
+    }
+
+    /// Limits reads to `bytes` per second.
+    pub fn read_bps(mut self, bytes: u64) -> Self {
+        self.read_bps = Some(bytes);
+        self
+    }
+
+    /// Limits writes to `bytes` per second.
+    pub fn write_bps(mut self, bytes: u64) -> Self {
+        self.write_bps = Some(bytes);
+        self
+    }
+
+    /// Limits reads to `ops` operations per second.
+    pub fn read_iops(mut self, ops: u64) -> Self {
+        self.read_iops = Some(ops);
+        self
+    }
+
+    /// Limits writes to `ops` operations per second.
+    pub fn write_iops(mut self, ops: u64) -> Self {
+        self.write_iops = Some(ops);
+        self
+    }
+
+    fn to_line(self, major: u32, minor: u32) -> String {
+        let limit = |value: Option<u64>| value.map_or("max".to_owned(), |v| v.to_string());
+        format!(
+            "{}:{} rbps={} wbps={} riops={} wiops={}",
+            major,
+            minor,
+            limit(self.read_bps),
+            limit(self.write_bps),
+            limit(self.read_iops),
+            limit(self.write_iops)
+        )
+    }
+}
+
+/// Resource limits applied to a sandbox through a cgroup of its own.
+///
+/// Every limit is left unset by default, and only the controllers needed for the limits which are
+/// set get enabled.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Resources {
+    parent: Option<PathBuf>,
+    memory_max: Option<u64>,
+    memory_swap_max: Option<u64>,
+    cpu_max: Option<(Duration, Duration)>,
+    pids_max: Option<u64>,
+    io_max: Vec<(u32, u32, IoLimit)>,
+}
+
+impl Resources {
+    pub fn new() -> Self {
+        Resources::default()
+    }
+
+    /// Creates the cgroup of the sandbox beneath `path`, an absolute path into the cgroup2
+    /// filesystem, rather than beneath the parent of the cgroup of the calling process.
+    pub fn parent<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.parent = Some(path.into());
+        self
+    }
+
+    /// Limits the memory usage of the sandbox to `bytes`, past which it is reclaimed from or OOM
+    /// killed, as per `memory.max`.
+    pub fn memory_max(mut self, bytes: u64) -> Self {
+        self.memory_max = Some(bytes);
+        self
+    }
+
+    /// Limits the swap usage of the sandbox to `bytes`, as per `memory.swap.max`. This requires
+    /// swap accounting to be enabled in the kernel.
+    pub fn memory_swap_max(mut self, bytes: u64) -> Self {
+        self.memory_swap_max = Some(bytes);
+        self
+    }
+
+    /// Lets the sandbox run for at most `quota` of CPU time in every `period`, as per `cpu.max`.
+    ///
+    /// For example, a `quota` of twice the `period` is worth two CPUs.
+    pub fn cpu_max(mut self, quota: Duration, period: Duration) -> Self {
+        self.cpu_max = Some((quota, period));
+        self
+    }
+
+    /// Limits the number of processes and threads in the sandbox, as per `pids.max`.
+    pub fn pids_max(mut self, max: u64) -> Self {
+        self.pids_max = Some(max);
+        self
+    }
+
+    /// Limits the I/O of the sandbox on the block device `major:minor`, as per `io.max`.
+    pub fn io_max(mut self, major: u32, minor: u32, limit: IoLimit) -> Self {
+        self.io_max.push((major, minor, limit));
+        self
+    }
+
+    fn controllers(&self) -> Vec<&'static str> {
+        let mut controllers = Vec::new();
+        if self.memory_max.is_some() || self.memory_swap_max.is_some() {
+            controllers.push("memory");
+        }
+        if self.cpu_max.is_some() {
+            controllers.push("cpu");
+        }
+        if self.pids_max.is_some() {
+            controllers.push("pids");
+        }
+        if !self.io_max.is_empty() {
+            controllers.push("io");
+        }
+
+        controllers
+    }
+
+    /// Returns the limits as pairs of cgroup interface file names and contents.
+    fn limits(&self) -> Vec<(&'static str, String)> {
+        let mut limits = Vec::new();
+        if let Some(bytes) = self.memory_max {
+            limits.push(("memory.max", bytes.to_string()));
+        }
+        if let Some(bytes) = self.memory_swap_max {
+            limits.push(("memory.swap.max", bytes.to_string()));
+        }
+        if let Some((quota, period)) = self.cpu_max {
+            let value = format!("{} {}", quota.as_micros(), period.as_micros());
+            limits.push(("cpu.max", value));
+        }
+        if let Some(max) = self.pids_max {
+            limits.push(("pids.max", max.to_string()));
+        }
+        for &(major, minor, limit) in &self.io_max {
+            limits.push(("io.max", limit.to_line(major, minor)));
+        }
+
+        limits
+    }
+}
+
+/// The cgroup of a single sandbox.
+#[derive(Debug)]
+pub(crate) struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Creates a cgroup with the given `resources` limits, and moves the process `pid` into it.
+    pub fn create(resources: &Resources, pid: pid_t) -> Result<Self, Error> {
+        let parent = match resources.parent {
+            Some(ref parent) => parent.clone(),
+            None => default_parent().map_err(Error::Cgroup)?,
+        };
+
+        enable_controllers(&parent, &resources.controllers()).map_err(Error::Cgroup)?;
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = parent.join(format!("bastille-{}-{}", process::id(), id));
+        fs::create_dir(&path).map_err(|e| Error::Cgroup(annotate(e, &path)))?;
+
+        let cgroup = Cgroup { path };
+        let result = resources
+            .limits()
+            .into_iter()
+            .chain(Some(("cgroup.procs", pid.to_string())))
+            .try_for_each(|(name, value)| {
+                let path = cgroup.path.join(name);
+                write_file(&path, &value).map_err(|e| annotate(e, &path))
+            });
+
+        match result {
+            Ok(()) => Ok(cgroup),
+            // The process hasn't started running the command yet, so it is fine to leave it behind
+            // in the cgroup we came from, and to drop the empty cgroup.
+            Err(error) => Err(Error::Cgroup(error)),
+        }
+    }
+
+    /// Kills anything left in the cgroup and removes it.
+    ///
+    /// This may block for a moment, waiting for the killed processes to be gone.
+    pub fn remove(self) {
+        // `cgroup.kill` is only available since Linux 5.14. Without it, removing the cgroup fails
+        // if the sandbox left any processes behind.
+        let _ = write_file(&self.path.join("cgroup.kill"), "1");
+
+        // The kernel takes a moment to notice that killed processes are gone.
+        for _ in 0..100 {
+            match fs::remove_dir(&self.path) {
+                Err(ref e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+impl Drop for Cgroup {
+    /// Removes the cgroup if it is empty, without killing anything in it.
+    fn drop(&mut self) {
+        let _ = fs::remove_dir(&self.path);
+    }
+}
+
+/// Returns the path of the cgroup of the calling process, which must be on the unified hierarchy.
+fn current_cgroup() -> Result<PathBuf, io::Error> {
+    let root = CString::new(CGROUP_ROOT)?;
+    let mut stat: libc::statfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statfs(root.as_ptr(), &mut stat) } == -1
+        || stat.f_type as libc::c_long != CGROUP2_SUPER_MAGIC
+    {
+        let message = format!("no cgroup2 filesystem is mounted at `{}`", CGROUP_ROOT);
+        return Err(io::Error::new(ErrorKind::Unsupported, message));
+    }
+
+    let cgroups = fs::read_to_string("/proc/self/cgroup")?;
+    let path = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "not in a cgroup v2 hierarchy"))?;
+
+    Ok(Path::new(CGROUP_ROOT).join(path.trim_start_matches('/')))
+}
+
+/// Returns the parent of the cgroup of the calling process, which doesn't hold the calling process
+/// itself, or the root cgroup if that is where the calling process lives.
+fn default_parent() -> Result<PathBuf, io::Error> {
+    let current = current_cgroup()?;
+    match current.parent() {
+        Some(parent) if current != Path::new(CGROUP_ROOT) => Ok(parent.to_owned()),
+        _ => Ok(current),
+    }
+}
+
+/// Makes sure `controllers` are enabled for the children of the cgroup at `parent`.
+fn enable_controllers(parent: &Path, controllers: &[&str]) -> Result<(), io::Error> {
+    let path = parent.join("cgroup.subtree_control");
+    let enabled = fs::read_to_string(&path).map_err(|e| annotate(e, &path))?;
+    let enabled: Vec<_> = enabled.split_whitespace().collect();
+
+    for controller in controllers {
+        if enabled.contains(controller) {
+            continue;
+        }
+
+        match write_file(&path, &format!("+{}", controller)) {
+            Err(ref e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                let message = format!(
+                    "cannot enable the {} controller beneath `{}`, since it holds processes; \
+                     move them into a leaf cgroup or pass another parent",
+                    controller,
+                    parent.display()
+                );
+                return Err(io::Error::other(message));
+            }
+            other => other.map_err(|e| annotate(e, &path))?,
+        }
+    }
+
+    Ok(())
+}
+
+fn write_file(path: &Path, value: &str) -> Result<(), io::Error> {
+    OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(value.as_bytes()))
+}
+
+/// Adds the path of the cgroup interface file to an error, since the errno alone is cryptic.
+fn annotate(error: io::Error, path: &Path) -> io::Error {
+    let message = format!("`{}`: {}", path.display(), error);
+    io::Error::new(error.kind(), message)
+}
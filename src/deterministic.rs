@@ -0,0 +1,133 @@
+//! Settings which make sandboxed commands behave the same on every run (Linux only).
+//!
+//! See `Sandbox::deterministic()` for the settings applied. Some of them depend on the running
+//! kernel, and rather than failing to spawn, those it couldn't honour are reported through
+//! `Child::unhonored()`.
+
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+use std::{io, mem};
+
+use libc::{c_ulong, gid_t, mode_t, uid_t};
+
+pub(crate) const HOSTNAME: &str = "localhost";
+pub(crate) const DOMAINNAME: &str = "localdomain";
+pub(crate) const UID: uid_t = 1000;
+pub(crate) const GID: gid_t = 1000;
+pub(crate) const UMASK: mode_t = 0o022;
+const CWD: &str = "/";
+
+/// The environment of the command, before the variables set on the `Command` itself.
+const ENV: &[(&str, &str)] = &[
+    ("HOME", CWD),
+    ("LC_ALL", "C"),
+    ("PATH", "/usr/local/bin:/usr/bin:/bin"),
+    ("SOURCE_DATE_EPOCH", "0"),
+    ("TZ", "UTC"),
+];
+
+/// A deterministic setting which the kernel couldn't honour.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Unhonored {
+    /// The command runs as some other uid or gid than the fixed one, e.g. since the user namespace
+    /// isn't unshared.
+    Ids,
+    /// Address space layout randomization couldn't be disabled with `personality()`.
+    AddressRandomization,
+    /// The monotonic and boot time clocks couldn't be zeroed, e.g. on kernels older than Linux 5.6
+    /// which lack time namespaces.
+    Clocks,
+}
+
+impl Unhonored {
+    pub(crate) fn to_raw(self) -> u8 {
+        match self {
+            Unhonored::Ids => 0,
+            Unhonored::AddressRandomization => 1,
+            Unhonored::Clocks => 2,
+        }
+    }
+
+    pub(crate) fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(Unhonored::Ids),
+            1 => Some(Unhonored::AddressRandomization),
+            2 => Some(Unhonored::Clocks),
+            _ => None,
+        }
+    }
+}
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+/// A time namespace clock offset, as seconds and nanoseconds.
+pub(crate) type ClockOffset = (i64, i64);
+
+/// Returns the time namespace offsets which make the monotonic and boot time clocks start out at
+/// zero, give or take the time it takes to enter the namespace.
+pub(crate) fn clock_offsets() -> Result<(ClockOffset, ClockOffset), io::Error> {
+    Ok((
+        negated_clock(libc::CLOCK_MONOTONIC)?,
+        negated_clock(libc::CLOCK_BOOTTIME)?,
+    ))
+}
+
+fn negated_clock(clock: libc::clockid_t) -> Result<ClockOffset, io::Error> {
+    let mut time: libc::timespec = unsafe { mem::zeroed() };
+    if unsafe { libc::clock_gettime(clock, &mut time) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // The kernel only takes nanoseconds from 0 up to a second, so borrow one from the seconds.
+    let (secs, nanos) = (time.tv_sec as i64, time.tv_nsec as i64);
+    if nanos == 0 {
+        Ok((-secs, 0))
+    } else {
+        Ok((-secs - 1, NANOS_PER_SEC - nanos))
+    }
+}
+
+/// Disables address space layout randomization for the calling process and its children,
+/// returning whether the kernel went along with it.
+pub(crate) unsafe fn disable_aslr() -> bool {
+    // Passing an invalid persona only queries the current one.
+    let current = libc::personality(0xffff_ffff);
+    if current == -1 {
+        return false;
+    }
+
+    // Some seccomp profiles reject the call, and others make it a no-op, so check that it stuck.
+    let persona = (current | libc::ADDR_NO_RANDOMIZE) as c_ulong;
+    libc::personality(persona) != -1
+        && libc::personality(0xffff_ffff) & libc::ADDR_NO_RANDOMIZE != 0
+}
+
+/// Replaces the inherited environment and working directory of `command` with fixed ones.
+///
+/// Variables and the working directory set on the `Command` itself take precedence. The standard
+/// library passes the resulting environment to the command sorted by name.
+pub(crate) fn scrub_command(command: &mut Command) {
+    let explicit: Vec<(OsString, Option<OsString>)> = command
+        .get_envs()
+        .map(|(key, value)| (key.to_owned(), value.map(|v| v.to_owned())))
+        .collect();
+
+    let cwd = command
+        .get_current_dir()
+        .unwrap_or_else(|| Path::new(CWD))
+        .to_owned();
+
+    command
+        .env_clear()
+        .envs(ENV.iter().copied())
+        .env("PWD", &cwd);
+    for (key, value) in explicit {
+        match value {
+            Some(value) => command.env(key, value),
+            None => command.env_remove(key),
+        };
+    }
+
+    command.current_dir(cwd);
+}
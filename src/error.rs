@@ -0,0 +1,133 @@
+use std::error::Error as StdError;
+use std::ffi::{NulError, OsString};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io;
+use std::path::PathBuf;
+
+use crate::MappingError;
+
+/// An error which occurred while setting up or spawning a sandbox.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The running kernel was built without user namespace support.
+    UserNamespacesUnsupported,
+    /// User namespaces are supported, but `/proc/sys/user/max_user_namespaces` is set to 0.
+    MaxUserNamespacesZero,
+    /// The sandboxed process could not be cloned.
+    Clone(io::Error),
+    /// Acquiring, switching or dropping privileges failed.
+    Privileges(io::Error),
+    /// The uid/gid map of the sandbox user namespace could not be written.
+    UidMap(io::Error),
+    /// A namespace could not be unshared from inside the sandbox.
+    Namespace(io::Error),
+    /// A filesystem could not be mounted, remounted or pivoted to inside the sandbox.
+    Mount {
+        source: PathBuf,
+        dest: PathBuf,
+        error: io::Error,
+    },
+    /// A mapping would expose a `procfs` mount, which requires `allow_sysctl(true)`.
+    ProcfsDenied(PathBuf),
+    /// The loopback device of the sandbox network namespace could not be configured.
+    Netlink(io::Error),
+    /// A seccomp policy could not be compiled or installed.
+    Seccomp(io::Error),
+    /// A Landlock ruleset could not be created or enforced.
+    Landlock(io::Error),
+    /// The cgroup of the sandbox could not be created or configured.
+    Cgroup(io::Error),
+    /// A resource limit could not be applied to the sandboxed process.
+    Rlimit(io::Error),
+    /// The command could not be executed inside the sandbox.
+    Exec { program: OsString, error: io::Error },
+    /// A path mapping was invalid.
+    Mapping(MappingError),
+    /// Any other I/O error.
+    Io(io::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match *self {
+            Error::UserNamespacesUnsupported => write!(fmt, "user namespaces are unsupported"),
+            Error::MaxUserNamespacesZero => write!(fmt, "max user namespaces is set to 0"),
+            Error::Clone(ref e) => write!(fmt, "unable to clone() process: {}", e),
+            Error::Privileges(ref e) => write!(fmt, "failed to adjust privileges: {}", e),
+            Error::UidMap(ref e) => write!(fmt, "failed to set up uid/gid map: {}", e),
+            Error::Namespace(ref e) => write!(fmt, "failed to unshare namespace: {}", e),
+            Error::Mount {
+                ref source,
+                ref dest,
+                ref error,
+            } => write!(
+                fmt,
+                "failed to mount `{}` at `{}`: {}",
+                source.to_string_lossy(),
+                dest.to_string_lossy(),
+                error
+            ),
+            Error::ProcfsDenied(ref path) => write!(
+                fmt,
+                "mounting procfs at `{}` is not permitted",
+                path.to_string_lossy()
+            ),
+            Error::Netlink(ref e) => write!(fmt, "failed to set up loopback device: {}", e),
+            Error::Seccomp(ref e) => write!(fmt, "failed to set up seccomp filter: {}", e),
+            Error::Landlock(ref e) => write!(fmt, "failed to set up Landlock ruleset: {}", e),
+            Error::Cgroup(ref e) => write!(fmt, "failed to set up cgroup: {}", e),
+            Error::Rlimit(ref e) => write!(fmt, "failed to set resource limit: {}", e),
+            Error::Exec {
+                ref program,
+                ref error,
+            } => write!(
+                fmt,
+                "failed to execute `{}`: {}",
+                program.to_string_lossy(),
+                error
+            ),
+            Error::Mapping(ref e) => write!(fmt, "{}", e),
+            Error::Io(ref e) => write!(fmt, "{}", e),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            Error::UserNamespacesUnsupported | Error::MaxUserNamespacesZero => None,
+            Error::ProcfsDenied(_) => None,
+            Error::Clone(ref e)
+            | Error::Privileges(ref e)
+            | Error::UidMap(ref e)
+            | Error::Namespace(ref e)
+            | Error::Netlink(ref e)
+            | Error::Seccomp(ref e)
+            | Error::Landlock(ref e)
+            | Error::Cgroup(ref e)
+            | Error::Rlimit(ref e)
+            | Error::Io(ref e) => Some(e),
+            Error::Mount { ref error, .. } | Error::Exec { ref error, .. } => Some(error),
+            Error::Mapping(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<NulError> for Error {
+    fn from(error: NulError) -> Self {
+        Error::Io(error.into())
+    }
+}
+
+impl From<MappingError> for Error {
+    fn from(error: MappingError) -> Self {
+        Error::Mapping(error)
+    }
+}
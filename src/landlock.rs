@@ -0,0 +1,469 @@
+//! Landlock access control for sandboxed processes (Linux 5.13+ only).
+//!
+//! The bind mounts making up the filesystem view of the sandbox are its primary boundary. Landlock
+//! adds a second one underneath, which still holds if the mount namespace setup is ever wrong: the
+//! sandboxed process may only access the mapped paths, with the rights derived from each `Mapping`.
+//!
+//! Since Linux 6.7, Landlock can also restrict which TCP ports the sandboxed process may bind to
+//! and connect to, which is useful when it shares the network namespace of the host.
+//!
+//! Since Linux 6.12, the sandboxed process is also scoped to its own abstract UNIX sockets and its
+//! own processes unless `Sandbox::scope_ipc()` is disabled, even if it shares the network or PID
+//! namespace of the host.
+//!
+//! The rights which can be restricted depend on the Landlock ABI version of the running kernel.
+//! The best version available is picked when the sandbox is spawned, and the rights which ended up
+//! being enforced are reported through `Child::landlock()`.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, ErrorKind};
+use std::ops::{BitAnd, BitOr, BitOrAssign};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::{mem, ptr};
+
+use libc::{c_int, c_long, c_void};
+
+use crate::{util, Error};
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: c_int = 1;
+const LANDLOCK_RULE_NET_PORT: c_int = 2;
+
+/// Mirrors `struct landlock_ruleset_attr`. Older kernels accept it as long as the fields they
+/// don't know about are zero.
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+    handled_access_net: u64,
+    scoped: u64,
+}
+
+/// Mirrors `struct landlock_path_beneath_attr`, which is packed in the kernel headers.
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Mirrors `struct landlock_net_port_attr`.
+#[repr(C, packed)]
+struct NetPortAttr {
+    allowed_access: u64,
+    port: u64,
+}
+
+/// A set of filesystem access rights, as defined by `LANDLOCK_ACCESS_FS_*`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct AccessFs(u64);
+
+impl AccessFs {
+    pub const EXECUTE: AccessFs = AccessFs(1 << 0);
+    pub const WRITE_FILE: AccessFs = AccessFs(1 << 1);
+    pub const READ_FILE: AccessFs = AccessFs(1 << 2);
+    pub const READ_DIR: AccessFs = AccessFs(1 << 3);
+    pub const REMOVE_DIR: AccessFs = AccessFs(1 << 4);
+    pub const REMOVE_FILE: AccessFs = AccessFs(1 << 5);
+    pub const MAKE_CHAR: AccessFs = AccessFs(1 << 6);
+    pub const MAKE_DIR: AccessFs = AccessFs(1 << 7);
+    pub const MAKE_REG: AccessFs = AccessFs(1 << 8);
+    pub const MAKE_SOCK: AccessFs = AccessFs(1 << 9);
+    pub const MAKE_FIFO: AccessFs = AccessFs(1 << 10);
+    pub const MAKE_BLOCK: AccessFs = AccessFs(1 << 11);
+    pub const MAKE_SYM: AccessFs = AccessFs(1 << 12);
+    /// Linking or renaming a file into another directory (ABI v2).
+    pub const REFER: AccessFs = AccessFs(1 << 13);
+    /// Truncating a file (ABI v3).
+    pub const TRUNCATE: AccessFs = AccessFs(1 << 14);
+    /// Calling `ioctl()` on a device file (ABI v5).
+    pub const IOCTL_DEV: AccessFs = AccessFs(1 << 15);
+
+    /// The rights which also apply to files, rather than only to directories.
+    const FILE: AccessFs = AccessFs(
+        Self::EXECUTE.0
+            | Self::WRITE_FILE.0
+            | Self::READ_FILE.0
+            | Self::TRUNCATE.0
+            | Self::IOCTL_DEV.0,
+    );
+
+    const READ: AccessFs = AccessFs(Self::READ_FILE.0 | Self::READ_DIR.0 | Self::IOCTL_DEV.0);
+
+    const WRITE: AccessFs = AccessFs(
+        Self::WRITE_FILE.0
+            | Self::REMOVE_DIR.0
+            | Self::REMOVE_FILE.0
+            | Self::MAKE_CHAR.0
+            | Self::MAKE_DIR.0
+            | Self::MAKE_REG.0
+            | Self::MAKE_SOCK.0
+            | Self::MAKE_FIFO.0
+            | Self::MAKE_BLOCK.0
+            | Self::MAKE_SYM.0
+            | Self::REFER.0
+            | Self::TRUNCATE.0,
+    );
+
+    pub const fn empty() -> Self {
+        AccessFs(0)
+    }
+
+    /// Returns every right known to the given Landlock ABI version.
+    pub fn for_abi(abi: u32) -> Self {
+        match abi {
+            0 => AccessFs::empty(),
+            1 => AccessFs((1 << 13) - 1),
+            2 => AccessFs((1 << 14) - 1),
+            3 | 4 => AccessFs((1 << 15) - 1),
+            _ => AccessFs((1 << 16) - 1),
+        }
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, other: AccessFs) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for AccessFs {
+    type Output = AccessFs;
+
+    fn bitor(self, rhs: AccessFs) -> AccessFs {
+        AccessFs(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for AccessFs {
+    fn bitor_assign(&mut self, rhs: AccessFs) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for AccessFs {
+    type Output = AccessFs;
+
+    fn bitand(self, rhs: AccessFs) -> AccessFs {
+        AccessFs(self.0 & rhs.0)
+    }
+}
+
+/// A set of network access rights, as defined by `LANDLOCK_ACCESS_NET_*` (ABI v4).
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct AccessNet(u64);
+
+impl AccessNet {
+    /// Binding a TCP socket to a local port.
+    pub const BIND_TCP: AccessNet = AccessNet(1 << 0);
+    /// Connecting a TCP socket to a remote port.
+    pub const CONNECT_TCP: AccessNet = AccessNet(1 << 1);
+
+    pub const fn empty() -> Self {
+        AccessNet(0)
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, other: AccessNet) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for AccessNet {
+    type Output = AccessNet;
+
+    fn bitor(self, rhs: AccessNet) -> AccessNet {
+        AccessNet(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for AccessNet {
+    fn bitor_assign(&mut self, rhs: AccessNet) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// A set of IPC scopes, as defined by `LANDLOCK_SCOPE_*` (ABI v6).
+///
+/// A scoped process can only reach the processes of its own Landlock domain, i.e. the sandbox,
+/// through the given means.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Scope(u64);
+
+impl Scope {
+    /// Connecting to abstract UNIX sockets, such as those of D-Bus and X11 in the host network
+    /// namespace.
+    pub const ABSTRACT_UNIX_SOCKET: Scope = Scope(1 << 0);
+    /// Sending signals.
+    pub const SIGNAL: Scope = Scope(1 << 1);
+
+    pub const fn empty() -> Self {
+        Scope(0)
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, other: Scope) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Scope {
+    type Output = Scope;
+
+    fn bitor(self, rhs: Scope) -> Scope {
+        Scope(self.0 | rhs.0)
+    }
+}
+
+/// The Landlock restrictions in effect for a sandboxed process.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Status {
+    abi: u32,
+    handled_fs: AccessFs,
+    handled_net: AccessNet,
+    scoped: Scope,
+}
+
+impl Status {
+    /// Returns the Landlock ABI version which was used, or 0 if Landlock is unavailable.
+    pub fn abi(&self) -> u32 {
+        self.abi
+    }
+
+    /// Returns the filesystem rights which are restricted, all of which are denied outside of the
+    /// mapped paths. Any right missing here is left unrestricted by the kernel.
+    pub fn handled_fs(&self) -> AccessFs {
+        self.handled_fs
+    }
+
+    /// Returns the network rights which are restricted, all of which are denied except for the
+    /// allowed ports.
+    pub fn handled_net(&self) -> AccessNet {
+        self.handled_net
+    }
+
+    /// Returns the IPC scopes the process is confined to.
+    pub fn scoped(&self) -> Scope {
+        self.scoped
+    }
+
+    /// Returns whether any restriction is enforced at all.
+    pub fn is_enforced(&self) -> bool {
+        !self.handled_fs.is_empty() || !self.handled_net.is_empty() || !self.scoped.is_empty()
+    }
+}
+
+/// Queries the Landlock ABI version of the running kernel, which is 0 if Landlock is unavailable.
+pub(crate) fn abi_version() -> u32 {
+    let abi = unsafe { create_ruleset(ptr::null(), 0, LANDLOCK_CREATE_RULESET_VERSION) };
+
+    // Kernels built without Landlock fail with `ENOSYS`, and `EOPNOTSUPP` means it is disabled at
+    // boot time. Either way, there is nothing to enforce.
+    if abi < 0 {
+        0
+    } else {
+        abi as u32
+    }
+}
+
+/// A set of paths inside the sandbox along with the rights granted beneath each of them, and of
+/// the TCP ports which may be bound or connected to.
+///
+/// Filesystem access is only restricted if any path was added, and binding or connecting only if
+/// the respective list of ports was set, even if it is empty. IPC is only scoped if asked for.
+#[derive(Debug, Default)]
+pub(crate) struct Ruleset {
+    paths: Vec<(CString, AccessFs)>,
+    bind_ports: Option<Vec<u16>>,
+    connect_ports: Option<Vec<u16>>,
+    scoped: bool,
+}
+
+impl Ruleset {
+    /// Grants access beneath `path`, readable and optionally `writable` and `executable`.
+    pub fn allow(&mut self, path: &Path, writable: bool, executable: bool) -> Result<(), Error> {
+        let mut access = AccessFs::READ;
+        if writable {
+            access |= AccessFs::WRITE;
+        }
+        if executable {
+            access |= AccessFs::EXECUTE;
+        }
+
+        self.allow_access(path, access)
+    }
+
+    /// Grants `access` beneath `path`.
+    pub fn allow_access(&mut self, path: &Path, access: AccessFs) -> Result<(), Error> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        self.paths.push((path, access));
+        Ok(())
+    }
+
+    /// Only allows binding TCP sockets to the given `ports`.
+    pub fn tcp_bind_ports(&mut self, ports: &[u16]) {
+        self.bind_ports = Some(ports.to_vec());
+    }
+
+    /// Only allows connecting TCP sockets to the given `ports`.
+    pub fn tcp_connect_ports(&mut self, ports: &[u16]) {
+        self.connect_ports = Some(ports.to_vec());
+    }
+
+    /// Scopes abstract UNIX sockets and signals to the Landlock domain of the sandbox.
+    pub fn scope_ipc(&mut self) {
+        self.scoped = true;
+    }
+
+    /// Determines which of the rights covered by this set the kernel with the given Landlock `abi`
+    /// version is able to restrict.
+    ///
+    /// Filesystem rights and scopes are restricted on a best-effort basis, whereas port allowlists
+    /// fail with an error on kernels older than Linux 6.7, which would silently leave the network
+    /// open. Scoping is applied if the kernel supports it, since Linux 6.12.
+    pub fn status(&self, abi: u32) -> Result<Status, Error> {
+        let handled_fs = if self.paths.is_empty() {
+            AccessFs::empty()
+        } else {
+            AccessFs::for_abi(abi)
+        };
+
+        let mut handled_net = AccessNet::empty();
+        if self.bind_ports.is_some() {
+            handled_net |= AccessNet::BIND_TCP;
+        }
+        if self.connect_ports.is_some() {
+            handled_net |= AccessNet::CONNECT_TCP;
+        }
+
+        if !handled_net.is_empty() && abi < 4 {
+            let message = format!(
+                "TCP port rules require Landlock ABI v4 or newer, but the kernel supports v{}",
+                abi
+            );
+            return Err(Error::Landlock(io::Error::new(
+                ErrorKind::Unsupported,
+                message,
+            )));
+        }
+
+        let scoped = if self.scoped && abi >= 6 {
+            Scope::ABSTRACT_UNIX_SOCKET | Scope::SIGNAL
+        } else {
+            Scope::empty()
+        };
+
+        Ok(Status {
+            abi,
+            handled_fs,
+            handled_net,
+            scoped,
+        })
+    }
+
+    /// Restricts the calling thread and its future children to the rules of this set. This
+    /// requires `PR_SET_NO_NEW_PRIVS` to be set.
+    pub unsafe fn restrict_self(&self, status: &Status) -> Result<(), Error> {
+        if !status.is_enforced() {
+            return Ok(());
+        }
+
+        let attr = RulesetAttr {
+            handled_access_fs: status.handled_fs.bits(),
+            handled_access_net: status.handled_net.bits(),
+            scoped: status.scoped.bits(),
+        };
+        let fd = create_ruleset(&attr, mem::size_of::<RulesetAttr>(), 0);
+        let ruleset = File::from_raw_fd(util::catch_io_error(fd).map_err(Error::Landlock)?);
+
+        for (path, access) in &self.paths {
+            // Mount points such as `/dev/mqueue` are skipped on read-only mappings of `/dev`, and
+            // there is nothing to grant beneath a path which doesn't exist.
+            let fd = libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC);
+            let parent = match util::catch_io_error(fd) {
+                Ok(fd) => File::from_raw_fd(fd),
+                Err(ref e) if e.raw_os_error() == Some(libc::ENOENT) => continue,
+                Err(e) => return Err(Error::Landlock(e)),
+            };
+
+            // Rules on anything but a directory may only carry rights which apply to files.
+            let mut stat: libc::stat = mem::zeroed();
+            util::catch_io_error(libc::fstat(parent.as_raw_fd(), &mut stat))
+                .map_err(Error::Landlock)?;
+            let mut allowed = *access & status.handled_fs;
+            if stat.st_mode & libc::S_IFMT != libc::S_IFDIR {
+                allowed = allowed & AccessFs::FILE;
+            }
+            if allowed.is_empty() {
+                continue;
+            }
+
+            let attr = PathBeneathAttr {
+                allowed_access: allowed.bits(),
+                parent_fd: parent.as_raw_fd(),
+            };
+            add_rule(&ruleset, LANDLOCK_RULE_PATH_BENEATH, &attr)?;
+        }
+
+        let ports = self
+            .bind_ports
+            .iter()
+            .flatten()
+            .map(|&p| (p, AccessNet::BIND_TCP));
+        let ports = ports.chain(
+            self.connect_ports
+                .iter()
+                .flatten()
+                .map(|&p| (p, AccessNet::CONNECT_TCP)),
+        );
+        for (port, access) in ports {
+            let attr = NetPortAttr {
+                allowed_access: access.bits(),
+                port: port as u64,
+            };
+            add_rule(&ruleset, LANDLOCK_RULE_NET_PORT, &attr)?;
+        }
+
+        let res = libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0);
+        util::catch_io_error(res as c_int).map_err(Error::Landlock)?;
+        Ok(())
+    }
+}
+
+unsafe fn add_rule<T>(ruleset: &File, rule_type: c_int, attr: &T) -> Result<(), Error> {
+    let res = libc::syscall(
+        libc::SYS_landlock_add_rule,
+        ruleset.as_raw_fd(),
+        rule_type,
+        attr as *const T as *const c_void,
+        0,
+    );
+    util::catch_io_error(res as c_int).map_err(Error::Landlock)?;
+    Ok(())
+}
+
+unsafe fn create_ruleset(attr: *const RulesetAttr, size: usize, flags: u32) -> c_int {
+    let res: c_long = libc::syscall(libc::SYS_landlock_create_ruleset, attr, size, flags);
+    res as c_int
+}
@@ -1,21 +1,56 @@
 use std::collections::HashSet;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use std::io::Error;
+use std::io;
 use std::path::{Component, Path, PathBuf};
 use std::process::Command;
 
-use self::process::Child;
+pub use self::error::Error;
+pub use self::namespaces::Namespaces;
 
+use self::process::{Child, Stdio};
+
+#[cfg(target_os = "linux")]
+pub mod cgroup;
+#[cfg(target_os = "linux")]
+pub mod deterministic;
+#[cfg(target_os = "linux")]
+pub mod landlock;
 pub mod process;
+pub mod rlimit;
+#[cfg(target_os = "linux")]
+pub mod seccomp;
 
+mod error;
+mod namespaces;
 mod os;
 mod util;
 
+/// How the sandboxed process is kept from injecting input into the terminal of the parent with
+/// `ioctl(TIOCSTI)`, as in CVE-2017-5226.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TerminalIsolation {
+    /// Detaches the process from the terminal with `setsid()`. This breaks job control in
+    /// interactive shells.
+    NewSession,
+    /// Rejects `ioctl(TIOCSTI)` and `ioctl(TIOCLINUX)` with a seccomp filter. This is the default,
+    /// and has no effect on macOS.
+    Seccomp,
+    /// Leaves the terminal exposed.
+    None,
+}
+
+impl Default for TerminalIsolation {
+    fn default() -> Self {
+        TerminalIsolation::Seccomp
+    }
+}
+
 #[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Mapping {
     sandbox: PathBuf,
     host: PathBuf,
     writable: bool,
+    executable: bool,
 }
 
 impl Mapping {
@@ -53,9 +88,19 @@ impl Mapping {
             sandbox: sandbox_path,
             host: host.into(),
             writable,
+            executable: true,
         })
     }
 
+    /// Sets whether files beneath this mapping may be executed, which is the default.
+    ///
+    /// Otherwise, the mapping is mounted with `MS_NOEXEC` on Linux, and execution is also denied
+    /// by the Landlock ruleset if `Sandbox::landlock()` is enabled.
+    pub fn executable(mut self, enabled: bool) -> Self {
+        self.executable = enabled;
+        self
+    }
+
     pub fn sandbox_path(&self) -> &Path {
         &self.sandbox
     }
@@ -67,6 +112,10 @@ impl Mapping {
     pub fn is_writable(&self) -> bool {
         self.writable
     }
+
+    pub fn is_executable(&self) -> bool {
+        self.executable
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -115,7 +164,7 @@ impl Mappings {
         self.0.clear()
     }
 
-    pub fn resolve_symlinks(&self) -> Result<Vec<Mapping>, Error> {
+    pub fn resolve_symlinks(&self) -> Result<Vec<Mapping>, io::Error> {
         self.0
             .clone()
             .into_iter()
@@ -141,10 +190,39 @@ pub struct Sandbox {
     directories: HashSet<PathBuf>,
     allow_devices: bool,
     allow_local_sockets: bool,
-    allow_network: bool,
     allow_sysctl: bool,
+    namespaces: Namespaces,
+    hostname: Option<String>,
+    domainname: Option<String>,
+    shm_size: Option<u64>,
+    cgroupfs: bool,
+    die_with_parent: bool,
+    init: bool,
+    terminal_isolation: TerminalIsolation,
+    #[cfg(target_os = "linux")]
+    seccomp: Option<seccomp::Policy>,
+    #[cfg(target_os = "linux")]
+    hardening: Option<seccomp::Hardening>,
+    #[cfg(target_os = "linux")]
+    landlock: bool,
+    #[cfg(target_os = "linux")]
+    scope_ipc: bool,
+    #[cfg(target_os = "linux")]
+    tcp_bind_ports: Option<Vec<u16>>,
+    #[cfg(target_os = "linux")]
+    tcp_connect_ports: Option<Vec<u16>>,
+    #[cfg(target_os = "linux")]
+    resources: Option<cgroup::Resources>,
+    #[cfg(target_os = "linux")]
+    time_offsets: Option<(i64, i64)>,
+    #[cfg(target_os = "linux")]
+    deterministic: bool,
+    rlimits: Vec<rlimit::Rlimit>,
     uid: Option<u32>,
     gid: Option<u32>,
+    stdin: Stdio,
+    stdout: Stdio,
+    stderr: Stdio,
 }
 
 impl Sandbox {
@@ -157,8 +235,37 @@ impl Sandbox {
             gid: None,
             allow_devices: false,
             allow_local_sockets: false,
-            allow_network: false,
             allow_sysctl: false,
+            namespaces: Namespaces::default(),
+            hostname: None,
+            domainname: None,
+            shm_size: None,
+            cgroupfs: false,
+            die_with_parent: false,
+            init: false,
+            terminal_isolation: TerminalIsolation::default(),
+            #[cfg(target_os = "linux")]
+            seccomp: None,
+            #[cfg(target_os = "linux")]
+            hardening: None,
+            #[cfg(target_os = "linux")]
+            landlock: false,
+            #[cfg(target_os = "linux")]
+            scope_ipc: true,
+            #[cfg(target_os = "linux")]
+            tcp_bind_ports: None,
+            #[cfg(target_os = "linux")]
+            tcp_connect_ports: None,
+            #[cfg(target_os = "linux")]
+            resources: None,
+            #[cfg(target_os = "linux")]
+            time_offsets: None,
+            #[cfg(target_os = "linux")]
+            deterministic: false,
+            rlimits: Vec::new(),
+            stdin: Stdio::inherit(),
+            stdout: Stdio::inherit(),
+            stderr: Stdio::inherit(),
         }
     }
 
@@ -229,24 +336,286 @@ impl Sandbox {
         self
     }
 
+    /// Allows the sandboxed process to create `AF_UNIX` sockets, which lets it connect to any
+    /// socket it can see on the filesystem.
+    ///
+    /// On Linux, this is enforced with a seccomp filter rejecting `socket()` and `socketpair()`
+    /// for `AF_UNIX` with `EPERM`. On 32-bit x86, powerpc64 and s390x, creating sockets of
+    /// any family through `socketcall()` is rejected as well, since the filter can't tell their
+    /// family apart.
     pub fn allow_local_sockets(&mut self, enabled: bool) -> &mut Self {
         self.allow_local_sockets = enabled;
         self
     }
 
+    /// Shares the network namespace of the host with the sandbox. This is a shorthand for
+    /// toggling `Namespaces::net()`.
     pub fn allow_network(&mut self, enabled: bool) -> &mut Self {
-        self.allow_network = enabled;
+        self.namespaces.net = !enabled;
         self
     }
 
+    /// Allows mounting `procfs` into the sandbox, and shares the PID namespace of the host with it.
+    /// The latter is a shorthand for toggling `Namespaces::pid()`.
     pub fn allow_sysctl(&mut self, enabled: bool) -> &mut Self {
         self.allow_sysctl = enabled;
+        self.namespaces.pid = !enabled;
+        self
+    }
+
+    /// Selects the namespaces to unshare, replacing any change made by `allow_network()` and
+    /// `allow_sysctl()` so far.
+    pub fn namespaces(&mut self, namespaces: Namespaces) -> &mut Self {
+        self.namespaces = namespaces;
+        self
+    }
+
+    /// Sets the hostname seen inside the sandbox, which implies unsharing the UTS namespace. This
+    /// has no effect on macOS.
+    pub fn hostname<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        self.hostname = Some(name.into());
+        self
+    }
+
+    /// Sets the NIS domain name seen inside the sandbox, which implies unsharing the UTS
+    /// namespace. This has no effect on macOS.
+    pub fn domainname<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        self.domainname = Some(name.into());
+        self
+    }
+
+    /// Limits the size of the `tmpfs` mounted at `/dev/shm`, in bytes. By default, it may take up
+    /// to half of the RAM, like any other `tmpfs`.
+    ///
+    /// Whenever the IPC namespace is unshared, a fresh `/dev/mqueue` is mounted into the sandbox,
+    /// along with a fresh `/dev/shm` if its size is set or a mapping already provides the
+    /// directory. Either is skipped if a read-only mapping of `/dev` lacks the directory. This has
+    /// no effect on macOS.
+    pub fn shm_size(&mut self, bytes: u64) -> &mut Self {
+        self.shm_size = Some(bytes);
+        self
+    }
+
+    /// Mounts a read-only cgroup2 filesystem at `/sys/fs/cgroup`, which requires a cgroup
+    /// namespace.
+    ///
+    /// It only shows the cgroup of the sandbox and its descendants, which lets tools like the JVM
+    /// detect the limits set with `resources()`. This has no effect on macOS.
+    pub fn cgroupfs(&mut self, enabled: bool) -> &mut Self {
+        self.cgroupfs = enabled;
+        self
+    }
+
+    /// Kills the sandboxed process with `SIGKILL` when the spawning thread exits.
+    ///
+    /// Note that this follows the thread which called `spawn()`, not the whole process, as per
+    /// `PR_SET_PDEATHSIG`. Sandboxes spawned from short-lived threads will die along with them.
+    /// This has no effect on macOS.
+    pub fn die_with_parent(&mut self, enabled: bool) -> &mut Self {
+        self.die_with_parent = enabled;
+        self
+    }
+
+    /// Runs a minimal init inside the sandbox as the parent of the command.
+    ///
+    /// It reaps orphaned processes and forwards signals to the command, or to every process in the
+    /// sandbox with a PID namespace of its own. The sandboxed process then exits the same way as
+    /// the command, dying from the same signal if the command was killed by one. As PID 1 of its
+    /// own PID namespace, which the kernel won't let signal itself, init exits with 128 plus the
+    /// signal number instead. Either way, init reports how the command exited to
+    /// `Child::exceeded_rlimit()` through a pipe of its own. Init is
+    /// subject to the same Landlock ruleset, seccomp filters and resource limits as the command, so
+    /// a seccomp policy must allow it to `fork()`. Unsharing the time namespace implies this. This
+    /// has no effect on macOS.
+    pub fn init(&mut self, enabled: bool) -> &mut Self {
+        self.init = enabled;
+        self
+    }
+
+    /// Filters the system calls of the sandboxed command through the given seccomp `policy`.
+    #[cfg(target_os = "linux")]
+    pub fn seccomp(&mut self, policy: seccomp::Policy) -> &mut Self {
+        self.seccomp = Some(policy);
+        self
+    }
+
+    /// Blocks the dangerous system calls selected by `hardening` inside the sandbox.
+    ///
+    /// Pass `Hardening::default()` to get the same protection as Bubblewrap and Flatpak.
+    #[cfg(target_os = "linux")]
+    pub fn hardened_syscalls(&mut self, hardening: seccomp::Hardening) -> &mut Self {
+        self.hardening = Some(hardening);
+        self
+    }
+
+    /// Restricts filesystem access with a Landlock ruleset derived from the mappings, in addition
+    /// to the bind mounts.
+    ///
+    /// Beneath each mapping, files may be read, written if the mapping is writable, and executed
+    /// if it is executable. Directories created with `directory()` and the private `/dev/shm` and
+    /// `/dev/mqueue` are writable but not executable, and everything else, including listing the
+    /// root directory, is denied.
+    ///
+    /// On kernels without Landlock, only the bind mounts apply; check `Child::landlock()` for the
+    /// rights which were actually enforced.
+    #[cfg(target_os = "linux")]
+    pub fn landlock(&mut self, enabled: bool) -> &mut Self {
+        self.landlock = enabled;
+        self
+    }
+
+    /// Scopes abstract UNIX sockets and signals to the sandbox with Landlock (Linux 6.12+), so
+    /// that it can neither connect to the abstract sockets of the host nor signal its processes,
+    /// even if it shares the network or PID namespace. Enabled by default.
+    ///
+    /// This is independent of `landlock()`. Check `Child::landlock()` for whether the scopes were
+    /// actually enforced.
+    #[cfg(target_os = "linux")]
+    pub fn scope_ipc(&mut self, enabled: bool) -> &mut Self {
+        self.scope_ipc = enabled;
+        self
+    }
+
+    /// Only allows the sandboxed process to bind TCP sockets to the given `ports`.
+    ///
+    /// This is meant for use with `allow_network(true)`, where the sandbox shares the network
+    /// namespace of the host. It is enforced with Landlock, and spawning fails on kernels older
+    /// than Linux 6.7 rather than leaving the network open. UDP and other protocols are
+    /// unaffected.
+    #[cfg(target_os = "linux")]
+    pub fn tcp_bind_ports<I: IntoIterator<Item = u16>>(&mut self, ports: I) -> &mut Self {
+        self.tcp_bind_ports = Some(ports.into_iter().collect());
+        self
+    }
+
+    /// Only allows the sandboxed process to connect TCP sockets to the given `ports`, on any host.
+    ///
+    /// Like `tcp_bind_ports()`, this is enforced with Landlock and requires Linux 6.7 or newer.
+    #[cfg(target_os = "linux")]
+    pub fn tcp_connect_ports<I: IntoIterator<Item = u16>>(&mut self, ports: I) -> &mut Self {
+        self.tcp_connect_ports = Some(ports.into_iter().collect());
+        self
+    }
+
+    /// Limits the resources available to the sandbox with a cgroup of its own, which is removed
+    /// once the sandboxed process has been reaped.
+    ///
+    /// A `Child` dropped without waiting for it leaves its cgroup behind, unless the sandbox has
+    /// already exited by then.
+    #[cfg(target_os = "linux")]
+    pub fn resources(&mut self, resources: cgroup::Resources) -> &mut Self {
+        self.resources = Some(resources);
+        self
+    }
+
+    /// Shifts `CLOCK_MONOTONIC` and `CLOCK_BOOTTIME` inside the sandbox by the given number of
+    /// seconds, which may be negative.
+    ///
+    /// This implies unsharing the time namespace, and spawning fails on kernels older than Linux
+    /// 5.6. The offsets apply to every process in the sandbox, and a clock may not be shifted
+    /// below zero; for example, an offset of minus the uptime of the host makes the clocks start
+    /// out near zero.
+    #[cfg(target_os = "linux")]
+    pub fn time_offsets(&mut self, monotonic: i64, boottime: i64) -> &mut Self {
+        self.time_offsets = Some((monotonic, boottime));
+        self
+    }
+
+    /// Makes the sandboxed command behave the same on every run, e.g. for reproducible builds.
+    ///
+    /// This applies the following settings, unless they are configured otherwise:
+    ///
+    /// * The hostname `localhost` and the domain name `localdomain`.
+    /// * The uid and gid 1000.
+    /// * Address space layout randomization disabled with `personality(ADDR_NO_RANDOMIZE)`.
+    /// * An environment holding only `HOME=/`, `LC_ALL=C`, `PATH`, `PWD`, `SOURCE_DATE_EPOCH=0`
+    ///   and `TZ=UTC`, in sorted order, plus the variables set on the `Command`.
+    /// * The working directory `/`, unless set on the `Command`, and the umask `022`.
+    /// * The monotonic and boot time clocks starting out at zero in a time namespace of their
+    ///   own, unless `time_offsets()` is given. This implies running an init.
+    ///
+    /// The environment and working directory are set on the `Command` passed to `spawn()` itself,
+    /// which keeps them afterwards.
+    ///
+    /// Spawning doesn't fail if the kernel can't honour some of them; check `Child::unhonored()`
+    /// for those which were left out.
+    #[cfg(target_os = "linux")]
+    pub fn deterministic(&mut self, enabled: bool) -> &mut Self {
+        self.deterministic = enabled;
+        self
+    }
+
+    /// Limits `resource` for the sandboxed command to `soft`, which it may raise up to `hard`.
+    ///
+    /// Limits are applied right before the command is executed, in the order they were given, and
+    /// are inherited by its children. Pass `rlimit::INFINITY` to lift a limit. Unlike
+    /// `resources()`, this works without cgroup delegation, but only limits each process on its
+    /// own. Check `Child::exceeded_rlimit()` to tell whether the command was killed for exceeding
+    /// a limit.
+    pub fn rlimit(&mut self, resource: rlimit::Resource, soft: u64, hard: u64) -> &mut Self {
+        self.rlimits.push(rlimit::Rlimit {
+            resource,
+            soft,
+            hard,
+        });
+        self
+    }
+
+    /// Sets how the sandboxed command is kept from injecting input into the terminal of the parent.
+    ///
+    /// `TerminalIsolation::Seccomp` is the default, and rejects `ioctl(TIOCSTI)` and
+    /// `ioctl(TIOCLINUX)` with `EPERM` while keeping the command in the session of the parent, so
+    /// job control in interactive shells still works. It has no effect on macOS, which lacks
+    /// seccomp. `TerminalIsolation::NewSession` calls `setsid()` instead, detaching the command
+    /// from the controlling terminal altogether on every platform. `TerminalIsolation::None` does
+    /// neither, and should only be used for trusted commands.
+    pub fn terminal_isolation(&mut self, mode: TerminalIsolation) -> &mut Self {
+        self.terminal_isolation = mode;
+        self
+    }
+
+    /// Returns whether the time namespace is unshared, either explicitly or for its offsets.
+    #[cfg(target_os = "linux")]
+    fn unshares_time(&self) -> bool {
+        self.namespaces.time || self.time_offsets.is_some()
+    }
+
+    /// Returns whether an init is forked inside the sandbox, either explicitly or for a time
+    /// namespace which may be unshared.
+    #[cfg(target_os = "linux")]
+    fn forks_init(&self) -> bool {
+        self.init || self.unshares_time() || self.deterministic
+    }
+
+    pub fn stdin<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.stdin = cfg.into();
+        self
+    }
+
+    pub fn stdout<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.stdout = cfg.into();
+        self
+    }
+
+    pub fn stderr<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.stderr = cfg.into();
         self
     }
 
     pub fn spawn(&self, command: &mut Command) -> Result<Child, Error> {
         os::create_sandbox(self, command)
     }
+
+    /// Spawns `command` like `spawn()`, but returns a `Child` driven by the tokio runtime.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if it is not called from within a tokio runtime with I/O enabled.
+    #[cfg(all(feature = "tokio", target_os = "linux"))]
+    pub fn spawn_async(&self, command: &mut Command) -> Result<process::tokio::Child, Error> {
+        let child = self.spawn(command)?;
+        Ok(process::tokio::Child::from_std(child)?)
+    }
 }
 
 impl Default for Sandbox {
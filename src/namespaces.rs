@@ -0,0 +1,115 @@
+/// The set of Linux namespaces to unshare for the sandbox.
+///
+/// By default, every namespace except the time namespace is unshared. `Sandbox::allow_network()`
+/// and `Sandbox::allow_sysctl()` are shorthands for turning the network and PID namespaces off,
+/// respectively.
+///
+/// On macOS, only `net()` has an effect: network access is denied while it is enabled.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Namespaces {
+    pub(crate) user: bool,
+    pub(crate) mount: bool,
+    pub(crate) pid: bool,
+    pub(crate) net: bool,
+    pub(crate) uts: bool,
+    pub(crate) ipc: bool,
+    pub(crate) cgroup: bool,
+    pub(crate) time: bool,
+}
+
+impl Namespaces {
+    pub fn new() -> Self {
+        Namespaces {
+            user: true,
+            mount: true,
+            pid: true,
+            net: true,
+            uts: true,
+            ipc: true,
+            cgroup: true,
+            time: false,
+        }
+    }
+
+    /// Returns a set with every namespace shared with the host.
+    pub fn none() -> Self {
+        Namespaces {
+            user: false,
+            mount: false,
+            pid: false,
+            net: false,
+            uts: false,
+            ipc: false,
+            cgroup: false,
+            time: false,
+        }
+    }
+
+    /// Unshares the user namespace, which lets unprivileged users set up the other namespaces.
+    ///
+    /// Without it, spawning only succeeds if the process is privileged, e.g. installed `setuid`.
+    pub fn user(mut self, enabled: bool) -> Self {
+        self.user = enabled;
+        self
+    }
+
+    /// Unshares the mount namespace, which is required for any mapping, soft link or directory.
+    pub fn mount(mut self, enabled: bool) -> Self {
+        self.mount = enabled;
+        self
+    }
+
+    /// Unshares the PID namespace, hiding every process outside of the sandbox.
+    pub fn pid(mut self, enabled: bool) -> Self {
+        self.pid = enabled;
+        self
+    }
+
+    /// Unshares the network namespace, leaving the sandbox with only a loopback device.
+    pub fn net(mut self, enabled: bool) -> Self {
+        self.net = enabled;
+        self
+    }
+
+    /// Unshares the UTS namespace, so the hostname and domain name can't be changed for the host.
+    ///
+    /// Setting `Sandbox::hostname()` or `Sandbox::domainname()` implies this.
+    pub fn uts(mut self, enabled: bool) -> Self {
+        self.uts = enabled;
+        self
+    }
+
+    /// Unshares the IPC namespace, isolating System V IPC objects and POSIX message queues.
+    ///
+    /// This also mounts a private `/dev/shm` and `/dev/mqueue` into the sandbox, if the mount
+    /// namespace is unshared as well.
+    pub fn ipc(mut self, enabled: bool) -> Self {
+        self.ipc = enabled;
+        self
+    }
+
+    /// Unshares the cgroup namespace, hiding the cgroup hierarchy above that of the sandbox.
+    ///
+    /// With `Sandbox::resources()`, the root of the namespace is the cgroup created for the
+    /// sandbox. Otherwise, it is the cgroup of the calling process.
+    pub fn cgroup(mut self, enabled: bool) -> Self {
+        self.cgroup = enabled;
+        self
+    }
+
+    /// Unshares the time namespace (Linux 5.6+).
+    ///
+    /// Since only the children of the unsharing process enter a new time namespace, this implies
+    /// running an init inside the sandbox, as with `Sandbox::init(true)`. Setting
+    /// `Sandbox::time_offsets()` implies this.
+    pub fn time(mut self, enabled: bool) -> Self {
+        self.time = enabled;
+        self
+    }
+}
+
+impl Default for Namespaces {
+    fn default() -> Self {
+        Namespaces::new()
+    }
+}
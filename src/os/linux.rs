@@ -1,62 +1,113 @@
 //! FIXME: Need to refactor this to be safer. Currently we are following the design of Bubblewrap
 //! very closely until feature parity, but refactoring to a safer Rust API will follow.
 
-use std::io::{Error, ErrorKind};
+use std::convert::Infallible;
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::io::AsRawFd;
 use std::os::unix::process::CommandExt;
+use std::path::Path;
 use std::process::Command;
+use std::sync::{Mutex, PoisonError};
 
 use caps::Capability;
-use ipc_channel::ipc;
 use libc::{gid_t, uid_t};
 use openat::Dir;
 
-use crate::process::Child;
-#[cfg(feature = "piped")]
-use crate::process::ChildStderr;
-#[cfg(any(feature = "piped", feature = "piped-merged"))]
-use crate::process::{ChildStdin, ChildStdout};
-use crate::{util, Sandbox};
+use crate::cgroup::Cgroup;
+use crate::deterministic::{self, ClockOffset, Unhonored};
+use crate::process::{self, Child};
+use crate::{landlock, rlimit, seccomp, util, Error, Sandbox, TerminalIsolation};
 
 mod creds;
+mod init;
 mod net;
 mod privs;
+mod report;
 mod unshare;
 
-// Used by `privs::try_acquire_privs()`.
-static mut REAL_UID: uid_t = 0;
-static mut REAL_GID: gid_t = 0;
-static mut OVERFLOW_UID: uid_t = 0;
-static mut OVERFLOW_GID: gid_t = 0;
-static mut IS_PRIVILEGED: bool = false;
-static mut REQUESTED_CAPS: Vec<Capability> = Vec::new();
+/// `libc` only defines this for glibc targets.
+const CLONE_NEWTIME: libc::c_int = 0x80;
+
+/// Held from creating the pipes of a sandbox until the parent has closed the ends which belong to
+/// the child, so that they don't leak into a sandbox spawned by another thread meanwhile.
+static SPAWN_LOCK: Mutex<()> = Mutex::new(());
+
+/// Per-spawn state shared between the setup steps in the parent and in the child.
+///
+/// This used to live in global variables, which made it impossible to safely spawn several
+/// sandboxes with different configurations from multiple threads at once.
+#[derive(Debug)]
+pub struct Context {
+    real_uid: uid_t,
+    real_gid: gid_t,
+    overflow_uid: uid_t,
+    overflow_gid: gid_t,
+    is_privileged: bool,
+    requested_caps: Vec<Capability>,
+    sandbox_uid: uid_t,
+    sandbox_gid: gid_t,
+    proc_dir: Dir,
+}
+
+impl Context {
+    unsafe fn new(config: &Sandbox) -> Result<Self, Error> {
+        let real_uid = libc::getuid();
+        let real_gid = libc::getgid();
 
-static mut SANDBOX_UID: uid_t = -1i32 as uid_t;
-static mut SANDBOX_GID: gid_t = -1i32 as gid_t;
-static mut PROC_DIR: Option<Dir> = None;
+        // Note that `setfsuid` and `capset` are per-thread rather than per-process, so acquiring
+        // privileges should be safe in multithreaded scenarios with multiple sandboxes spawning! 🎉
+        let (is_privileged, requested_caps) =
+            privs::try_acquire_privs(real_uid).map_err(Error::Privileges)?;
+        let (overflow_uid, overflow_gid) = creds::read_overflow_ids()?;
+        let proc_dir = open_proc_dir()?;
+
+        let fixed_uid = Some(deterministic::UID).filter(|_| config.deterministic);
+        let fixed_gid = Some(deterministic::GID).filter(|_| config.deterministic);
+
+        Ok(Context {
+            real_uid,
+            real_gid,
+            overflow_uid,
+            overflow_gid,
+            is_privileged,
+            requested_caps,
+            sandbox_uid: config
+                .uid
+                .map(|uid| uid as uid_t)
+                .or(fixed_uid)
+                .unwrap_or(real_uid),
+            sandbox_gid: config
+                .gid
+                .map(|gid| gid as gid_t)
+                .or(fixed_gid)
+                .unwrap_or(real_gid),
+            proc_dir,
+        })
+    }
+}
 
 pub fn create_sandbox(config: &Sandbox, command: &mut Command) -> Result<Child, Error> {
     unsafe {
-        REAL_UID = libc::getuid();
-        REAL_GID = libc::getgid();
+        let ctx = Context::new(config)?;
+        let filters = seccomp_filters(config)?;
+        let (landlock_status, landlock_rules) = landlock_ruleset(config)?;
+        if config.deterministic {
+            deterministic::scrub_command(command);
+        }
 
-        // Note that `setfsuid` and `capset` are per-thread rather than per-process, so acquiring
-        // privileges should be safe in multithreaded scenarios with multiple sandboxes spawning! 🎉
-        privs::try_acquire_privs()?;
-        creds::read_overflow_ids()?;
-        open_proc_dir()?;
-
-        SANDBOX_UID = config.uid.map(|uid| uid as uid_t).unwrap_or(REAL_UID);
-        SANDBOX_GID = config.gid.map(|gid| gid as gid_t).unwrap_or(REAL_GID);
-
-        let (tx, rx) = ipc::channel()?;
-        #[cfg(any(feature = "piped", feature = "piped-merged"))]
-        let (stdin_r, stdin_w) = os_pipe::pipe()?;
-        #[cfg(any(feature = "piped", feature = "piped-merged"))]
-        let (stdout_r, stdout_w) = os_pipe::pipe()?;
-        #[cfg(feature = "piped")]
-        let (stderr_r, stderr_w) = os_pipe::pipe()?;
-
-        let pid = unshare::clone_process(&config)?;
+        let spawning = SPAWN_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let (mut sync_r, mut sync_w) = os_pipe::pipe()?;
+        let (mut report_r, mut report_w) = os_pipe::pipe()?;
+        let (child_pipes, parent_pipes) =
+            process::create_pipes(&config.stdin, &config.stdout, &config.stderr)?;
+        let (lifeline_r, lifeline_w) = os_pipe::pipe()?;
+        let (status_r, status_w) = if config.forks_init() {
+            os_pipe::pipe().map(|(r, w)| (Some(r), Some(w)))?
+        } else {
+            (None, None)
+        };
+
+        let (pid, pidfd) = unshare::clone_process(&config)?;
         if pid == 0 {
             // Child, in sandbox, privileged in the parent or in the user namespace (if
             // --unshare-user).
@@ -69,166 +120,443 @@ pub fn create_sandbox(config: &Sandbox, command: &mut Command) -> Result<Child,
             //
             // Unfortunately this also means you can't ptrace the final sandboxed process from
             // outside the sandbox either.
-
-            drop(tx);
-            #[cfg(any(feature = "piped", feature = "piped-merged"))]
-            drop(stdin_w);
-            #[cfg(any(feature = "piped", feature = "piped-merged"))]
-            drop(stdout_r);
-            #[cfg(feature = "piped")]
-            drop(stderr_r);
-
-            // Wait for the parent to init uid/gid maps and drop caps.
-            rx.recv().expect("Failed to communicate with parent");
-
-            // At this point we can completely drop root uid, but retain the required permitted
-            // caps. This allow us to do full setup as the user uid, which makes e.g. FUSE access
-            // work.
-            privs::switch_to_user_with_privs()?;
-            if !config.allow_network {
-                net::setup_loopback_device()?;
-            }
-
-            let mut ns_uid = SANDBOX_UID;
-            let mut ns_gid = SANDBOX_GID;
-            if !IS_PRIVILEGED {
-                // In the unprivileged case we have to write the uid/gid maps in the child, because
-                // we have no caps in the parent.
-
-                // TODO: Like with `bwrap`, we have to first map the `ns_uid` and `ns_gid` to 0,
-                // otherwise we can't mount the devpts filesystem (under the assumption we should
-                // allow dev access by default, which we are taking right now) because root is not
-                // mapped. Later, we will create another child user namespace and map back to the
-                // real uid. We should investigate whether this hack should even be necessary to
-                // perform conditionally, or perhaps we should just allow device access all the
-                // time. For now, we do the latter and always set `ns_uid` and `ns_gid` to 0.
-
-                ns_uid = 0;
-                ns_gid = 0;
-                creds::write_uid_gid_map(ns_uid, ns_gid, REAL_UID, REAL_GID, None, true, false)?;
-            }
-
-            let old_umask = libc::umask(0);
-
-            // Create our mounts and sandbox ourselves.
-            unshare::setup_environment(&config)?;
-
-            if ns_uid != SANDBOX_UID || ns_gid != SANDBOX_GID {
-                // Now that devpts is mounted and we no longer have a need for mount permissions,
-                // we can create a new userspace and map our uid 1:1.
-                util::catch_io_error(libc::unshare(libc::CLONE_NEWUSER))?;
-                creds::write_uid_gid_map(
-                    SANDBOX_UID,
-                    SANDBOX_GID,
-                    ns_uid,
-                    ns_gid,
-                    None,
-                    false,
-                    false,
-                )?;
-            }
-
-            // All privileged ops are done now, so drop caps that we don't need.
-            privs::drop_privs(!IS_PRIVILEGED)?;
-
-            // TODO: Set up seccomp filters here before restoring umask.
-            libc::umask(old_umask);
-
-            // Mitigate the CVE-2017-5226 sandbox escape by creating a new session ID. See below:
-            // https://github.com/containers/bubblewrap/issues/142
             //
-            // TODO: Investigate using `seccomp` filters like Flatpak does because the `setsid()`
-            // call breaks job control for some applications. We should also look for
-            // cross-platform equivalents for this operation, if any. Until we can set up a good
-            // solution for this, this code will remain commented out.
-            // util::catch_io_error(libc::setsid())?;
-
-            // TODO: Bubblewrap has an option called `opt_die_with_parent` which optionally allows
-            // the child process to die with SIGKILL when the parent dies. I assume it gets
-            // reparented to `init` otherwise. Here is the spot where this option gets enabled. We
-            // need to figure out whether this makes sense to enable/disable, given that Bastille
-            // is a library and not a free-standing binary.
-
-            if !IS_PRIVILEGED {
-                privs::set_ambient_capabilities()?;
-            }
-
-            // FIXME: Commands are currently statically forced to run in either inherit or piped
-            // mode until the `std::command::Command` builder offers some way to extract its fields
-            // and inspect them at run-time.
-            #[cfg(feature = "piped")]
-            let error = command
-                .stdin(stdin_r)
-                .stdout(stdout_w)
-                .stderr(stderr_w)
-                .exec();
-            #[cfg(feature = "piped-merged")]
-            let error = command
-                .stdin(stdin_r)
-                .stdout(stdout_w.try_clone()?)
-                .stderr(stdout_w)
-                .exec();
-            #[cfg(not(any(feature = "piped", feature = "piped-merged")))]
-            let error = command.exec();
-
-            Err(error)
+            // Unlike `fork()`, `clone()` leaves locks held by other threads of the caller locked
+            // in the child, including those of the allocator. Whatever the child needs is prepared
+            // above for this, and the steps below stick to system calls where possible.
+            // FIXME: The mount setup, capabilities and loopback device still allocate.
+
+            drop(sync_w);
+            drop(report_r);
+            drop(parent_pipes);
+            drop(lifeline_w);
+            drop(status_r);
+
+            // Any error past this point must never propagate back up into the caller, since we
+            // are a forked copy of it. Instead, report it to the parent and exit immediately.
+            let result = (|| -> Result<Infallible, Error> {
+                // Wait for the parent to init uid/gid maps and drop caps.
+                sync_r
+                    .read_exact(&mut [0])
+                    .map_err(|_| io::Error::from(ErrorKind::BrokenPipe))?;
+                drop(sync_r);
+
+                // The parent has moved us into our own cgroup by now, if it was asked to, which
+                // becomes the root of the namespace. Otherwise, it is the cgroup of the caller.
+                if config.namespaces.cgroup {
+                    util::catch_io_error(libc::unshare(libc::CLONE_NEWCGROUP))
+                        .map_err(Error::Namespace)?;
+                }
+
+                // At this point we can completely drop root uid, but retain the required
+                // permitted caps. This allow us to do full setup as the user uid, which makes e.g.
+                // FUSE access work.
+                privs::switch_to_user_with_privs(&ctx).map_err(Error::Privileges)?;
+                if config.namespaces.net {
+                    net::setup_loopback_device().map_err(Error::Netlink)?;
+                }
+
+                let mut ns_uid = ctx.sandbox_uid;
+                let mut ns_gid = ctx.sandbox_gid;
+                if config.namespaces.user && !ctx.is_privileged {
+                    // In the unprivileged case we have to write the uid/gid maps in the child,
+                    // because we have no caps in the parent.
+
+                    // TODO: Like with `bwrap`, we have to first map the `ns_uid` and `ns_gid` to
+                    // 0, otherwise we can't mount the devpts filesystem (under the assumption we
+                    // should allow dev access by default, which we are taking right now) because
+                    // root is not mapped. Later, we will create another child user namespace and
+                    // map back to the real uid. We should investigate whether this hack should
+                    // even be necessary to perform conditionally, or perhaps we should just allow
+                    // device access all the time. For now, we do the latter and always set
+                    // `ns_uid` and `ns_gid` to 0.
+
+                    ns_uid = 0;
+                    ns_gid = 0;
+                    creds::write_uid_gid_map(
+                        &ctx,
+                        ns_uid,
+                        ns_gid,
+                        ctx.real_uid,
+                        ctx.real_gid,
+                        None,
+                        true,
+                        false,
+                    )
+                    .map_err(Error::UidMap)?;
+                }
+
+                let old_umask = libc::umask(0);
+
+                // Create our mounts and sandbox ourselves.
+                if config.namespaces.mount {
+                    unshare::setup_environment(&ctx, &config)?;
+                }
+
+                // This has to happen before unsharing another user namespace below, since the UTS
+                // namespace belongs to the current one.
+                let fixed = |name| Some(name).filter(|_| config.deterministic);
+                let hostname = config
+                    .hostname
+                    .as_deref()
+                    .or_else(|| fixed(deterministic::HOSTNAME));
+                let domainname = config
+                    .domainname
+                    .as_deref()
+                    .or_else(|| fixed(deterministic::DOMAINNAME));
+                if let Some(name) = hostname {
+                    let name = name.as_bytes();
+                    util::catch_io_error(libc::sethostname(name.as_ptr() as *const _, name.len()))?;
+                }
+                if let Some(name) = domainname {
+                    let name = name.as_bytes();
+                    util::catch_io_error(libc::setdomainname(
+                        name.as_ptr() as *const _,
+                        name.len(),
+                    ))?;
+                }
+
+                if ns_uid != ctx.sandbox_uid || ns_gid != ctx.sandbox_gid {
+                    // Now that devpts is mounted and we no longer have a need for mount
+                    // permissions, we can create a new userspace and map our uid 1:1.
+                    util::catch_io_error(libc::unshare(libc::CLONE_NEWUSER))
+                        .map_err(Error::Namespace)?;
+                    creds::write_uid_gid_map(
+                        &ctx,
+                        ctx.sandbox_uid,
+                        ctx.sandbox_gid,
+                        ns_uid,
+                        ns_gid,
+                        None,
+                        false,
+                        false,
+                    )
+                    .map_err(Error::UidMap)?;
+                }
+
+                // Only our children enter the new time namespace, which is why we fork an init
+                // further below.
+                if config.unshares_time() {
+                    util::catch_io_error(libc::unshare(CLONE_NEWTIME)).map_err(Error::Namespace)?;
+
+                    // The offsets are frozen once the first process enters the namespace.
+                    if let Some((monotonic, boottime)) = config.time_offsets {
+                        write_time_offsets(&ctx, (monotonic, 0), (boottime, 0))
+                            .map_err(Error::Namespace)?;
+                    }
+                }
+
+                // Unlike explicit offsets, zeroing the clocks is best effort, since it depends on
+                // the kernel supporting time namespaces.
+                if config.deterministic
+                    && config.time_offsets.is_none()
+                    && !zero_clocks(&ctx, config.unshares_time())
+                {
+                    report::send_unhonored(&mut report_w, Unhonored::Clocks)?;
+                }
+
+                // All privileged ops are done now, so drop caps that we don't need.
+                privs::drop_privs(&ctx, !ctx.is_privileged).map_err(Error::Privileges)?;
+
+                if config.deterministic {
+                    if libc::getuid() != ctx.sandbox_uid || libc::getgid() != ctx.sandbox_gid {
+                        report::send_unhonored(&mut report_w, Unhonored::Ids)?;
+                    }
+                    libc::umask(deterministic::UMASK);
+                } else {
+                    libc::umask(old_umask);
+                }
+
+                // Mitigate the CVE-2017-5226 sandbox escape either by creating a new session ID,
+                // which breaks job control, or with a seccomp filter installed further below. See
+                // https://github.com/containers/bubblewrap/issues/142 for details.
+                if config.terminal_isolation == TerminalIsolation::NewSession {
+                    util::catch_io_error(libc::setsid())?;
+                }
+
+                // This must come after the last change of credentials, since that resets the
+                // parent death signal.
+                if config.die_with_parent {
+                    die_with_parent(&lifeline_r)?;
+                }
+                drop(lifeline_r);
+
+                if !ctx.is_privileged {
+                    privs::set_ambient_capabilities(&ctx).map_err(Error::Privileges)?;
+                }
+
+                if config.deterministic && !deterministic::disable_aslr() {
+                    report::send_unhonored(&mut report_w, Unhonored::AddressRandomization)?;
+                }
+
+                // These apply to init as well, which barely uses any resources of its own.
+                rlimit::apply(&config.rlimits).map_err(Error::Rlimit)?;
+
+                landlock_rules.restrict_self(&landlock_status)?;
+
+                // Install seccomp filters last, so they don't get in the way of the setup above.
+                // This relies on `PR_SET_NO_NEW_PRIVS`, which was set while acquiring privileges.
+                for filter in &filters {
+                    filter.install()?;
+                }
+
+                // Fork init only now, so it is as confined as the command and can't be used to
+                // escape the Landlock ruleset or the seccomp filters.
+                if let Some(ref status_w) = status_w {
+                    init::fork_command(config.die_with_parent, status_w.as_raw_fd())?;
+                }
+
+                child_pipes.apply(command);
+                let error = command.exec();
+
+                Err(Error::Exec {
+                    program: command.get_program().to_owned(),
+                    error,
+                })
+            })();
+
+            let error = match result {
+                Ok(never) => match never {},
+                Err(error) => error,
+            };
+
+            let _ = report::send(&mut report_w, &error);
+            libc::_exit(1);
         } else {
             // Parent, outside sandbox, privileged (initially). Discover namespace ids before we
             // drop privileges.
-
-            if IS_PRIVILEGED {
-                // We're running as euid 0, but the uid we want to map is not 0. This means we're
-                // not allowed to write this from the child user namespace, so we do it from the
-                // parent.
-                //
-                // Also, we map uid/gid 0 in the namespace (to overflowuid) if opt_needs_devpts is
-                // true, because otherwise the mount of devpts fails due to root not being mapped.
-                creds::write_uid_gid_map(
-                    SANDBOX_UID,
-                    SANDBOX_GID,
-                    REAL_UID,
-                    REAL_GID,
-                    Some(pid),
-                    true,
-                    true, // TODO: Decide whether to always allow /dev access in sandbox.
-                )?;
-            }
-
-            // Initial launched process, wait for exec:ed command to exit.
-
-            // We don't need any privileges in the launcher, drop them immediately.
-            privs::drop_privs(false)?;
-
-            // TODO: Bubblewrap has an option called `opt_die_with_parent` which optionally allows
-            // the child process to die with SIGKILL when the parent dies. I assume it gets
-            // reparented to `init` otherwise. Here is the spot where this option gets enabled. We
-            // need to figure out whether this makes sense to enable/disable, given that Bastille
-            // is a library and not a free-standing binary.
+            drop(sync_r);
+            drop(report_w);
+            drop(child_pipes);
+            drop(lifeline_r);
+            drop(status_w);
+            drop(spawning);
+
+            let result = (|| -> Result<Option<Cgroup>, Error> {
+                if ctx.is_privileged && config.namespaces.user {
+                    // We're running as euid 0, but the uid we want to map is not 0. This means
+                    // we're not allowed to write this from the child user namespace, so we do it
+                    // from the parent.
+                    //
+                    // Also, we map uid/gid 0 in the namespace (to overflowuid) if
+                    // opt_needs_devpts is true, because otherwise the mount of devpts fails due to
+                    // root not being mapped.
+                    creds::write_uid_gid_map(
+                        &ctx,
+                        ctx.sandbox_uid,
+                        ctx.sandbox_gid,
+                        ctx.real_uid,
+                        ctx.real_gid,
+                        Some(pid),
+                        true,
+                        true, // TODO: Decide whether to always allow /dev access in sandbox.
+                    )
+                    .map_err(Error::UidMap)?;
+                }
+
+                // Initial launched process, wait for exec:ed command to exit.
+
+                // We don't need any privileges in the launcher, drop them immediately.
+                privs::drop_privs(&ctx, false).map_err(Error::Privileges)?;
+
+                // Resource limits must be in place before the child runs any code of its own.
+                config
+                    .resources
+                    .as_ref()
+                    .map(|resources| Cgroup::create(resources, pid))
+                    .transpose()
+            })();
+
+            let cgroup = match result {
+                Ok(cgroup) => cgroup,
+                Err(error) => {
+                    // The child is still blocked waiting for us, so it is safe to tear it down.
+                    let _ = libc::kill(pid, libc::SIGKILL);
+                    reap(pid);
+                    return Err(error);
+                }
+            };
 
             // Notify child process that the uid/gid map has been written and to begin setup.
-            let _ = tx.send(());
-
-            #[cfg(any(feature = "piped", feature = "piped-merged"))]
-            let stdin = ChildStdin(stdin_w);
-            #[cfg(feature = "piped")]
-            let stdout = ChildStdout(stdout_r);
-            #[cfg(feature = "piped-merged")]
-            let stdout = ChildStdout(stdout_r);
-            #[cfg(feature = "piped")]
-            let stderr = ChildStderr(stderr_r);
-            #[cfg(feature = "piped-merged")]
-            let stderr = None;
-            #[cfg(not(any(feature = "piped", feature = "piped-merged")))]
-            let (stdin, stdout, stderr) = (None, None, None);
-
-            Ok(Child::from_parts(stdin, stdout, stderr, pid))
+            let _ = sync_w.write_all(&[0]);
+
+            // Block until the child either executes the command, closing the `CLOEXEC` report
+            // pipe, or reports back why it couldn't.
+            let report = match report::recv(&mut report_r) {
+                Ok((unhonored, None)) => Ok(unhonored),
+                Ok((_, Some(error))) => Err(error),
+                Err(error) => {
+                    // A malformed report leaves us unsure how far the child got, so make sure it
+                    // doesn't run the command.
+                    let _ = libc::kill(pid, libc::SIGKILL);
+                    Err(error.into())
+                }
+            };
+
+            let unhonored = match report {
+                Ok(unhonored) => unhonored,
+                Err(error) => {
+                    reap(pid);
+                    if let Some(cgroup) = cgroup {
+                        cgroup.remove();
+                    }
+                    return Err(error);
+                }
+            };
+
+            // The child has checked the lifeline by now, if it was asked to.
+            drop(lifeline_w);
+
+            let process::ParentPipes {
+                stdin,
+                stdout,
+                stderr,
+            } = parent_pipes;
+
+            let child = Child::from_parts(stdin, stdout, stderr, pid)
+                .with_pidfd(pidfd)
+                .with_pid_namespace(config.namespaces.pid)
+                .with_init(status_r)
+                .with_landlock(Some(landlock_status).filter(landlock::Status::is_enforced))
+                .with_cgroup(cgroup)
+                .with_unhonored(unhonored);
+
+            Ok(child)
+        }
+    }
+}
+
+/// Compiles the seccomp filters to install in the sandbox, so that errors surface before cloning.
+fn seccomp_filters(config: &Sandbox) -> Result<Vec<seccomp::Filter>, Error> {
+    let hardening = config.hardening.as_ref().map(|h| h.policy());
+    let terminal = match config.terminal_isolation {
+        TerminalIsolation::Seccomp => Some(seccomp::terminal_injection_policy()),
+        TerminalIsolation::NewSession | TerminalIsolation::None => None,
+    };
+    let local_sockets = if config.allow_local_sockets {
+        None
+    } else {
+        Some(seccomp::local_sockets_policy())
+    };
+
+    config
+        .seccomp
+        .iter()
+        .chain(hardening.as_ref())
+        .chain(terminal.as_ref())
+        .chain(local_sockets.as_ref())
+        .map(|policy| policy.compile())
+        .collect()
+}
+
+/// Derives the Landlock ruleset of the sandbox from its mappings and port allowlists, along with
+/// the rights which the running kernel is able to restrict.
+///
+/// Unless disabled, the ruleset also scopes abstract UNIX sockets and signals to the sandbox,
+/// which matters when it shares the network or PID namespace of the host.
+fn landlock_ruleset(config: &Sandbox) -> Result<(landlock::Status, landlock::Ruleset), Error> {
+    let mut ruleset = landlock::Ruleset::default();
+    if config.scope_ipc {
+        ruleset.scope_ipc();
+    }
+    if config.landlock {
+        for mapping in &config.mappings.0 {
+            ruleset.allow(
+                mapping.sandbox_path(),
+                mapping.is_writable(),
+                mapping.is_executable(),
+            )?;
+        }
+        for dir in &config.directories {
+            ruleset.allow(dir, true, false)?;
+        }
+        if config.namespaces.ipc && config.namespaces.mount {
+            // Neither of these may have been mounted, but a missing path is skipped.
+            ruleset.allow(Path::new("/dev/shm"), true, false)?;
+            ruleset.allow(Path::new("/dev/mqueue"), true, false)?;
         }
+        if config.cgroupfs {
+            ruleset.allow(Path::new("/sys/fs/cgroup"), false, false)?;
+        }
+    }
+    if let Some(ref ports) = config.tcp_bind_ports {
+        ruleset.tcp_bind_ports(ports);
     }
+    if let Some(ref ports) = config.tcp_connect_ports {
+        ruleset.tcp_connect_ports(ports);
+    }
+
+    let status = ruleset.status(landlock::abi_version())?;
+    Ok((status, ruleset))
 }
 
-unsafe fn open_proc_dir() -> Result<(), Error> {
-    PROC_DIR = Dir::open("/proc")
-        .map(Some)
-        .map_err(|_| Error::new(ErrorKind::Other, "Unable to open /proc dir"))?;
+/// Asks the kernel to `SIGKILL` us once the thread which spawned the sandbox exits.
+///
+/// The parent may already be gone by the time the `prctl()` takes effect. It holds the write end
+/// of the `lifeline` pipe until we have executed the command, so a hangup means it has died and
+/// we are on our own.
+unsafe fn die_with_parent(lifeline: &os_pipe::PipeReader) -> Result<(), Error> {
+    util::catch_io_error(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0))?;
+
+    let mut pollfd = libc::pollfd {
+        fd: lifeline.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    util::catch_io_error_repeat(|| libc::poll(&mut pollfd, 1, 0))?;
+    if pollfd.revents != 0 {
+        libc::_exit(1);
+    }
+
     Ok(())
 }
+
+/// Sets the clock offsets of the time namespace our children are going to enter.
+///
+/// This goes through the `/proc` of the host, since the sandbox may not have one mounted.
+unsafe fn write_time_offsets(
+    ctx: &Context,
+    monotonic: ClockOffset,
+    boottime: ClockOffset,
+) -> Result<(), io::Error> {
+    let mut offsets = [0; 128];
+    let capacity = offsets.len();
+    let mut rest = &mut offsets[..];
+    write!(
+        rest,
+        "{} {} {}\n{} {} {}\n",
+        libc::CLOCK_MONOTONIC,
+        monotonic.0,
+        monotonic.1,
+        libc::CLOCK_BOOTTIME,
+        boottime.0,
+        boottime.1
+    )?;
+    let len = capacity - rest.len();
+
+    creds::open_proc_file(ctx, None, "timens_offsets")
+        .and_then(|mut file| file.write_all(&offsets[..len]))
+}
+
+/// Makes the monotonic and boot time clocks start out at zero for our children, unsharing the time
+/// namespace first unless it already is. Returns whether the kernel went along with it.
+unsafe fn zero_clocks(ctx: &Context, unshared: bool) -> bool {
+    if !unshared && libc::unshare(CLONE_NEWTIME) == -1 {
+        return false;
+    }
+
+    deterministic::clock_offsets()
+        .and_then(|(monotonic, boottime)| write_time_offsets(ctx, monotonic, boottime))
+        .is_ok()
+}
+
+/// Reaps a child which failed to set up the sandbox, so it doesn't linger around as a zombie.
+fn reap(pid: libc::pid_t) {
+    let mut status = 0;
+    let _ = util::catch_io_error_repeat(|| unsafe { libc::waitpid(pid, &mut status, 0) });
+}
+
+fn open_proc_dir() -> Result<Dir, Error> {
+    Dir::open("/proc")
+        .map_err(|_| io::Error::new(ErrorKind::Other, "Unable to open /proc dir").into())
+}
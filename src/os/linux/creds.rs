@@ -1,28 +1,31 @@
-use std::fs;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::{Error, ErrorKind, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
 
-use libc::{c_uint, gid_t, pid_t, uid_t};
+use libc::{c_char, c_uint, gid_t, pid_t, uid_t};
 
-use super::{IS_PRIVILEGED, OVERFLOW_GID, OVERFLOW_UID, PROC_DIR};
+use super::Context;
 use crate::util;
 
-pub unsafe fn read_overflow_ids() -> Result<(), Error> {
+pub fn read_overflow_ids() -> Result<(uid_t, gid_t), Error> {
     let buf = fs::read_to_string("/proc/sys/kernel/overflowuid")?;
-    OVERFLOW_UID = buf
+    let overflow_uid = buf
         .trim()
         .parse()
         .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
 
     let buf = fs::read_to_string("/proc/sys/kernel/overflowgid")?;
-    OVERFLOW_GID = buf
+    let overflow_gid = buf
         .trim()
         .parse()
         .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
 
-    Ok(())
+    Ok((overflow_uid, overflow_gid))
 }
 
 pub unsafe fn write_uid_gid_map(
+    ctx: &Context,
     sandbox_uid: uid_t,
     sandbox_gid: gid_t,
     parent_uid: uid_t,
@@ -31,45 +34,52 @@ pub unsafe fn write_uid_gid_map(
     deny_groups: bool,
     map_root: bool,
 ) -> Result<(), Error> {
-    let ns_dir = {
-        let pid = pid
-            .map(|pid| pid.to_string())
-            .unwrap_or_else(|| "self".to_string());
-
-        let proc = PROC_DIR
-            .as_ref()
-            .ok_or_else(|| Error::new(ErrorKind::NotFound, "Expected /proc to be open"))?;
-
-        proc.read_link(pid).and_then(|path| proc.sub_dir(&path))?
-    };
-
+    // This runs in the sandboxed child, so the maps are formatted on the stack rather than
+    // allocated.
+    let mut uid_map = [0; 64];
     let uid_map = if map_root && parent_uid != 0 && sandbox_uid != 0 {
-        format!("0 {} 1\n{} {} 1\n", OVERFLOW_UID, sandbox_uid, parent_uid)
+        format_map(
+            &mut uid_map,
+            format_args!(
+                "0 {} 1\n{} {} 1\n",
+                ctx.overflow_uid, sandbox_uid, parent_uid
+            ),
+        )?
     } else {
-        format!("{} {} 1\n", sandbox_uid, parent_uid)
+        format_map(
+            &mut uid_map,
+            format_args!("{} {} 1\n", sandbox_uid, parent_uid),
+        )?
     };
 
+    let mut gid_map = [0; 64];
     let gid_map = if map_root && parent_gid != 0 && sandbox_gid != 0 {
-        format!("0 {} 1\n{} {} 1\n", OVERFLOW_GID, sandbox_gid, parent_gid)
+        format_map(
+            &mut gid_map,
+            format_args!(
+                "0 {} 1\n{} {} 1\n",
+                ctx.overflow_gid, sandbox_gid, parent_gid
+            ),
+        )?
     } else {
-        format!("{} {} 1\n", sandbox_gid, parent_gid)
+        format_map(
+            &mut gid_map,
+            format_args!("{} {} 1\n", sandbox_gid, parent_gid),
+        )?
     };
 
     // We have to be root to be allowed to write to the uid map for setuid apps, so temporary set
     // fsuid to 0.
-    let old_fsuid = if IS_PRIVILEGED {
+    let old_fsuid = if ctx.is_privileged {
         Some(util::catch_io_error(libc::setfsuid(0))? as c_uint)
     } else {
         None
     };
 
-    ns_dir
-        .update_file("uid_map", 0)
-        .and_then(|mut file| file.write_all(uid_map.as_bytes()))
-        .map_err(|_| Error::new(ErrorKind::Other, "Failed to set up uid map"))?;
+    open_proc_file(ctx, pid, "uid_map").and_then(|mut file| file.write_all(uid_map))?;
 
     if deny_groups {
-        let setgroups = ns_dir.update_file("setgroups", 0);
+        let setgroups = open_proc_file(ctx, pid, "setgroups");
         if let Err(err) = setgroups.and_then(|mut file| file.write_all(b"deny\n")) {
             // If /proc/[pid]/setgroups does not exist, assume we are
             // running a linux kernel < 3.19, i.e. we live with the
@@ -77,15 +87,12 @@ pub unsafe fn write_uid_gid_map(
             // where setgroups does not exist.
             match err.kind() {
                 ErrorKind::NotFound => {}
-                _ => return Err(Error::new(ErrorKind::Other, "Error writing to setgroups")),
+                _ => return Err(err),
             }
         }
     }
 
-    ns_dir
-        .update_file("gid_map", 0)
-        .and_then(|mut file| file.write_all(gid_map.as_bytes()))
-        .map_err(|_| Error::new(ErrorKind::Other, "Failed to set up gid map"))?;
+    open_proc_file(ctx, pid, "gid_map").and_then(|mut file| file.write_all(gid_map))?;
 
     if let Some(old) = old_fsuid {
         util::catch_io_error(libc::setfsuid(old))?;
@@ -93,3 +100,29 @@ pub unsafe fn write_uid_gid_map(
 
     Ok(())
 }
+
+/// Formats a uid or gid map into `buf`, returning the formatted part.
+fn format_map<'a>(buf: &'a mut [u8], map: fmt::Arguments) -> Result<&'a [u8], Error> {
+    let capacity = buf.len();
+    let mut rest = &mut buf[..];
+    rest.write_fmt(map)?;
+    let len = capacity - rest.len();
+    Ok(&buf[..len])
+}
+
+/// Opens `name` in the `/proc` directory of `pid`, or of the calling process, for writing.
+pub unsafe fn open_proc_file(ctx: &Context, pid: Option<pid_t>, name: &str) -> Result<File, Error> {
+    let mut path = [0; 64];
+    let mut rest = &mut path[..];
+    match pid {
+        Some(pid) => write!(rest, "{}/{}\0", pid, name)?,
+        None => write!(rest, "self/{}\0", name)?,
+    }
+
+    let fd = libc::openat(
+        ctx.proc_dir.as_raw_fd(),
+        path.as_ptr() as *const c_char,
+        libc::O_WRONLY | libc::O_CLOEXEC,
+    );
+    Ok(File::from_raw_fd(util::catch_io_error(fd)?))
+}
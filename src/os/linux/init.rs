@@ -0,0 +1,167 @@
+//! A minimal init for the sandbox, in the spirit of Bubblewrap's `do_init()` and tini.
+//!
+//! Inside a new PID namespace, the command would otherwise become PID 1. The kernel doesn't apply
+//! default signal dispositions to PID 1 and reparents orphaned processes to it, so commands which
+//! don't expect this ignore `SIGTERM` and leave zombies behind.
+
+use std::os::unix::io::RawFd;
+use std::{mem, ptr};
+
+use libc::{c_char, c_int, c_uint, c_void, pid_t, sigset_t};
+
+use crate::{util, Error};
+
+/// Where init keeps the status pipe, right past the standard streams.
+const STATUS_FD: c_int = 3;
+
+/// Forks the process which goes on to execute the command.
+///
+/// This returns in the new process, while the calling process becomes init and never returns. It
+/// forwards every signal it receives to the command, reaps any process reparented to it, and
+/// exits the same way as the command once the latter exits. Right before, it writes the raw wait
+/// status of the command into `status_pipe`, which must be `CLOEXEC`.
+pub unsafe fn fork_command(die_with_parent: bool, status_pipe: RawFd) -> Result<(), Error> {
+    // Block all signals before forking, so none can slip through before init is waiting for them.
+    let mut all: sigset_t = mem::zeroed();
+    let mut old: sigset_t = mem::zeroed();
+    libc::sigfillset(&mut all);
+    util::catch_io_error(libc::sigprocmask(libc::SIG_SETMASK, &all, &mut old))?;
+
+    let init = libc::getpid();
+    let command = util::catch_io_error(libc::fork())?;
+    if command == 0 {
+        util::catch_io_error(libc::sigprocmask(libc::SIG_SETMASK, &old, ptr::null_mut()))?;
+
+        // The parent death signal isn't inherited across `fork()`. If init has died in the
+        // meantime, we have already been reparented.
+        if die_with_parent {
+            util::catch_io_error(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0))?;
+            if libc::getppid() != init {
+                libc::_exit(1);
+            }
+        }
+
+        return Ok(());
+    }
+
+    // Anything else we hold on to would keep the parent from seeing EOF on the report and stdio
+    // pipes. That includes the standard streams, which may also be the terminal of the host, so
+    // point them at `/dev/null` rather than leaving them free for the next file we open.
+    libc::dup2(status_pipe, STATUS_FD);
+    close_fds_from(STATUS_FD + 1);
+    for fd in 0..STATUS_FD {
+        libc::close(fd);
+    }
+    reopen_null_streams();
+
+    // Without a PID namespace of our own, orphans are only reparented to us if we ask for it.
+    let _ = libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0);
+
+    let status = supervise(command, &all);
+    let _ = libc::write(
+        STATUS_FD,
+        &status as *const c_int as *const c_void,
+        mem::size_of::<c_int>(),
+    );
+    exit_like(status);
+}
+
+/// Waits for the command to exit, returning its wait status.
+unsafe fn supervise(command: pid_t, signals: &sigset_t) -> c_int {
+    loop {
+        let mut info: libc::siginfo_t = mem::zeroed();
+        let signal = libc::sigwaitinfo(signals, &mut info);
+        if signal == -1 {
+            continue;
+        } else if signal != libc::SIGCHLD {
+            // As PID 1 of our own namespace, pass the signal on to every process in it, which is
+            // what `Child::signal()` promises. `kill(-1)` never reaches outside the namespace.
+            let target = if libc::getpid() == 1 { -1 } else { command };
+            let _ = libc::kill(target, signal);
+            continue;
+        }
+
+        loop {
+            let mut status = 0;
+            let pid = libc::waitpid(-1, &mut status, libc::WNOHANG);
+            if pid <= 0 {
+                break;
+            } else if pid != command {
+                continue;
+            }
+
+            return status;
+        }
+    }
+}
+
+/// Exits with the wait `status` of the command.
+///
+/// If a signal killed the command, it is raised again with its default disposition, so our parent
+/// sees the same. The kernel doesn't let PID 1 of a namespace signal itself though, in which case
+/// we exit with 128 plus the signal number instead, like a shell. The parent can't tell that from
+/// an exit code, which is why the status is also written to the status pipe.
+unsafe fn exit_like(status: c_int) -> ! {
+    if libc::WIFEXITED(status) {
+        libc::_exit(libc::WEXITSTATUS(status));
+    } else if !libc::WIFSIGNALED(status) {
+        libc::_exit(1);
+    }
+
+    // Signals like `SIGXCPU` would otherwise dump the core of init.
+    let signal = libc::WTERMSIG(status);
+    let _ = libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+    libc::signal(signal, libc::SIG_DFL);
+
+    // All signals are still blocked, so this is only delivered once unblocked.
+    let mut set: sigset_t = mem::zeroed();
+    libc::sigemptyset(&mut set);
+    libc::sigaddset(&mut set, signal);
+    libc::raise(signal);
+    libc::sigprocmask(libc::SIG_UNBLOCK, &set, ptr::null_mut());
+
+    libc::_exit(128 + signal);
+}
+
+/// Opens `/dev/null` onto the standard streams, which must all be closed.
+///
+/// The sandbox may lack `/dev/null`, in which case a pipe which nobody ever uses takes them up
+/// instead, so nothing else lands on them.
+unsafe fn reopen_null_streams() {
+    // Either of these takes up fd 0, and the pipe fd 1 as well.
+    let null = b"/dev/null\0".as_ptr() as *const c_char;
+    let mut fds = [0; 2];
+    if libc::open(null, libc::O_RDWR) == -1 && libc::pipe(fds.as_mut_ptr()) == -1 {
+        return;
+    }
+
+    for fd in 1..=2 {
+        if libc::fcntl(fd, libc::F_GETFD) == -1 {
+            libc::dup2(0, fd);
+        }
+    }
+}
+
+unsafe fn close_fds_from(first: c_int) {
+    let res = libc::syscall(
+        libc::SYS_close_range,
+        first as c_uint,
+        c_uint::max_value(),
+        0,
+    );
+    if res == 0 {
+        return;
+    }
+
+    // `close_range()` is only available since Linux 5.9.
+    let mut limit: libc::rlimit = mem::zeroed();
+    let max = if libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) == 0 {
+        limit.rlim_cur.min(65536) as c_int
+    } else {
+        1024
+    };
+
+    for fd in first..max {
+        libc::close(fd);
+    }
+}
@@ -2,6 +2,7 @@ use std::ffi::CString;
 use std::io::{Error, ErrorKind};
 use std::os::unix::io::FromRawFd;
 use std::process;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 use log::{debug, trace};
 use netlink_packet_route::constants::{RTM_NEWADDR, RTM_NEWLINK};
@@ -101,8 +102,7 @@ fn create_new_address_message(index: u32) -> NetlinkMessage<RtnlMessage> {
         (len, NetlinkPayload::from(RtnlMessage::NewAddress(message)))
     };
 
-    let header =
-        unsafe { create_netlink_header(RTM_NEWADDR, NLM_F_CREATE | NLM_F_EXCL | NLM_F_ACK, len) };
+    let header = create_netlink_header(RTM_NEWADDR, NLM_F_CREATE | NLM_F_EXCL | NLM_F_ACK, len);
 
     let mut message = NetlinkMessage::new(header, payload);
     message.finalize();
@@ -125,18 +125,17 @@ fn create_new_link_message(index: u32) -> NetlinkMessage<RtnlMessage> {
         (len, NetlinkPayload::from(RtnlMessage::NewLink(message)))
     };
 
-    let header = unsafe { create_netlink_header(RTM_NEWLINK, NLM_F_ACK, len) };
+    let header = create_netlink_header(RTM_NEWLINK, NLM_F_ACK, len);
 
     let mut message = NetlinkMessage::new(header, payload);
     message.finalize();
     message
 }
 
-unsafe fn create_netlink_header(message_type: u16, flags: u16, length: u32) -> NetlinkHeader {
-    static mut COUNTER: u32 = 0;
+fn create_netlink_header(message_type: u16, flags: u16, length: u32) -> NetlinkHeader {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
 
-    let sequence_number = COUNTER;
-    COUNTER = COUNTER.wrapping_add(1);
+    let sequence_number = COUNTER.fetch_add(1, Ordering::Relaxed);
 
     NetlinkHeader {
         length,
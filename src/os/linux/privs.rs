@@ -5,15 +5,19 @@ use caps::{CapSet, Capability, CapsHashSet};
 use libc::uid_t;
 use log::debug;
 
-use super::{IS_PRIVILEGED, REAL_UID, REQUESTED_CAPS, SANDBOX_UID};
+use super::Context;
 use crate::util;
 
 // This acquires the privileges that Bastille will need to work. If this binary is not setuid, then
 // this does nothing, and it relies on unprivileged user namespaces to be used. This case is
-// `IS_PRIVILEGED = FALSE`.
-pub unsafe fn try_acquire_privs() -> Result<(), Error> {
+// `is_privileged = false`.
+//
+// Returns whether we are privileged, along with the capabilities requested for the sandbox.
+pub unsafe fn try_acquire_privs(real_uid: uid_t) -> Result<(bool, Vec<Capability>), Error> {
     let effective_uid = libc::geteuid();
-    let is_setuid = REAL_UID != effective_uid;
+    let is_setuid = real_uid != effective_uid;
+    let mut is_privileged = false;
+    let mut requested_caps = Vec::new();
 
     if is_setuid {
         if effective_uid != 0 {
@@ -21,7 +25,7 @@ pub unsafe fn try_acquire_privs() -> Result<(), Error> {
             return Err(Error::new(ErrorKind::Other, msg));
         }
 
-        IS_PRIVILEGED = true;
+        is_privileged = true;
 
         // We want to keep running as euid=0 until at the clone() operation because doing so will
         // make the user namespace be owned by root, which makes it not ptrace:able by the user as
@@ -30,33 +34,33 @@ pub unsafe fn try_acquire_privs() -> Result<(), Error> {
         //
         // However, we don't want to accidentally mis-use euid=0 for escalated filesystem access
         // before the clone(), so we set fsuid to the uid.
-        if libc::setfsuid(REAL_UID) < 0 {
+        if libc::setfsuid(real_uid) < 0 {
             return Err(Error::new(ErrorKind::Other, "Unable to set fsuid"));
         }
 
         // setfsuid can't properly report errors, check that it worked (as per manpage).
         let new_fsuid = libc::setfsuid(-1i32 as uid_t) as uid_t;
-        if new_fsuid != REAL_UID {
+        if new_fsuid != real_uid {
             let msg = format!("Unable to set fsuid (was {})", new_fsuid);
             return Err(Error::new(ErrorKind::Other, msg));
         }
 
         // We never need capabilities after execve(), so lets drop everything from the bounding
         // set.
-        drop_cap_bounding_set(true)?;
+        drop_cap_bounding_set(&[])?;
         // Keep only the required capabilities for setup.
         set_required_caps()?;
-    } else if REAL_UID != 0 && has_caps()? {
+    } else if real_uid != 0 && has_caps()? {
         // We have some capabilities in the non-setuid case, which should not happen. Probably
         // caused by the binary being setcap instead of setuid which we don't support anymore.
         let msg = "Unexpected capabilities but not setuid, maybe old file caps config?";
         return Err(Error::new(ErrorKind::Other, msg));
     } else {
-        if REAL_UID == 0 {
+        if real_uid == 0 {
             // If our uid is 0, default to inheriting all caps.
             let effective = caps::read(None, CapSet::Effective)
                 .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
-            REQUESTED_CAPS = effective.into_iter().collect();
+            requested_caps = effective.into_iter().collect();
         } else {
             // No worries, we will try unprivileged user namespaces later on.
         }
@@ -68,20 +72,20 @@ pub unsafe fn try_acquire_privs() -> Result<(), Error> {
         return Err(Error::new(ErrorKind::Other, msg));
     }
 
-    Ok(())
+    Ok((is_privileged, requested_caps))
 }
 
 // This is called once we're inside the namespace.
-pub unsafe fn switch_to_user_with_privs() -> Result<(), Error> {
+pub unsafe fn switch_to_user_with_privs(ctx: &Context) -> Result<(), Error> {
     // If we're in a new user namespace, we got back the bounding set, clear it again.
-    drop_cap_bounding_set(false)?;
-    if !IS_PRIVILEGED {
+    drop_cap_bounding_set(&ctx.requested_caps)?;
+    if !ctx.is_privileged {
         return Ok(());
     }
 
     // Tell kernel not clear capabilities when later dropping root uid.
     util::catch_io_error(libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0))?;
-    util::catch_io_error(libc::setuid(SANDBOX_UID))?;
+    util::catch_io_error(libc::setuid(ctx.sandbox_uid))?;
 
     // Regain effective required capabilities from permitted.
     set_required_caps()?;
@@ -90,31 +94,27 @@ pub unsafe fn switch_to_user_with_privs() -> Result<(), Error> {
 }
 
 // Call setuid() and use capset() to adjust capabilities.
-pub unsafe fn drop_privs(keep_requested_caps: bool) -> Result<(), Error> {
-    assert!(!keep_requested_caps || !IS_PRIVILEGED);
+pub unsafe fn drop_privs(ctx: &Context, keep_requested_caps: bool) -> Result<(), Error> {
+    assert!(!keep_requested_caps || !ctx.is_privileged);
 
     // Drop root uid.
     if libc::getuid() == 0 {
-        util::catch_io_error(libc::setuid(SANDBOX_UID))?;
+        util::catch_io_error(libc::setuid(ctx.sandbox_uid))?;
     }
 
-    drop_all_caps(keep_requested_caps)
+    drop_all_caps(ctx, keep_requested_caps)
 }
 
-pub unsafe fn set_ambient_capabilities() -> Result<(), Error> {
-    if IS_PRIVILEGED {
+pub fn set_ambient_capabilities(ctx: &Context) -> Result<(), Error> {
+    if ctx.is_privileged {
         Ok(())
     } else {
-        prctl_caps(REQUESTED_CAPS.as_slice(), false, true)
+        prctl_caps(ctx.requested_caps.as_slice(), false, true)
     }
 }
 
-unsafe fn drop_cap_bounding_set(drop_all: bool) -> Result<(), Error> {
-    if drop_all {
-        prctl_caps(&[], true, false)
-    } else {
-        prctl_caps(REQUESTED_CAPS.as_slice(), true, false)
-    }
+fn drop_cap_bounding_set(keep: &[Capability]) -> Result<(), Error> {
+    prctl_caps(keep, true, false)
 }
 
 fn set_required_caps() -> Result<(), Error> {
@@ -134,9 +134,9 @@ fn set_required_caps() -> Result<(), Error> {
     Ok(())
 }
 
-unsafe fn drop_all_caps(keep_requested_caps: bool) -> Result<(), Error> {
+fn drop_all_caps(ctx: &Context, keep_requested_caps: bool) -> Result<(), Error> {
     let requested = if keep_requested_caps {
-        CapsHashSet::from_iter(REQUESTED_CAPS.clone())
+        CapsHashSet::from_iter(ctx.requested_caps.clone())
     } else {
         CapsHashSet::new()
     };
@@ -155,7 +155,7 @@ unsafe fn drop_all_caps(keep_requested_caps: bool) -> Result<(), Error> {
     match result {
         Ok(_) => Ok(()),
         Err(e) => {
-            if e.kind() == ErrorKind::PermissionDenied && REAL_UID == 0 && !IS_PRIVILEGED {
+            if e.kind() == ErrorKind::PermissionDenied && ctx.real_uid == 0 && !ctx.is_privileged {
                 Ok(())
             } else {
                 Err(e)
@@ -0,0 +1,361 @@
+//! Reporting of setup failures from the sandboxed child back to the parent.
+//!
+//! The parent hands a `CLOEXEC` pipe to the child before cloning. If setup succeeds, the write end
+//! is closed implicitly by `execve()` and the parent reads EOF. If anything fails, the child
+//! writes a single encoded `Error` into the pipe and exits without ever returning into our code.
+//!
+//! Before either, the child may write any number of `Unhonored` deterministic settings, which
+//! don't stop it from executing the command.
+//!
+//! The child encodes reports on the stack, since it must not allocate. Paths and messages are cut
+//! off at `MAX_FIELD` bytes for this.
+
+use std::cmp;
+use std::ffi::OsString;
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::PathBuf;
+
+use os_pipe::{PipeReader, PipeWriter};
+
+use crate::deterministic::Unhonored;
+use crate::{Error, ErrorKind as MappingErrorKind, MappingError};
+
+const USER_NAMESPACES_UNSUPPORTED: u8 = 0;
+const MAX_USER_NAMESPACES_ZERO: u8 = 1;
+const CLONE: u8 = 2;
+const PRIVILEGES: u8 = 3;
+const UID_MAP: u8 = 4;
+const NAMESPACE: u8 = 5;
+const MOUNT: u8 = 6;
+const PROCFS_DENIED: u8 = 7;
+const NETLINK: u8 = 8;
+const EXEC: u8 = 9;
+const IO: u8 = 10;
+const SECCOMP: u8 = 11;
+const LANDLOCK: u8 = 12;
+const CGROUP: u8 = 13;
+const RLIMIT: u8 = 14;
+const UNHONORED: u8 = 15;
+const MAPPING: u8 = 16;
+
+const NOT_ABSOLUTE: u8 = 0;
+const NOT_NORMALIZED: u8 = 1;
+
+/// The most bytes of a path or message which are sent.
+const MAX_FIELD: usize = 1024;
+/// Room for the tag, errno and kind of an error along with up to three fields and their lengths.
+const MAX_REPORT: usize = 16 + 3 * (4 + MAX_FIELD);
+
+/// The kinds of errors without an errno which survive the trip, indexed by their encoding. Any
+/// other kind arrives as `ErrorKind::Other`.
+const KINDS: &[ErrorKind] = &[
+    ErrorKind::Other,
+    ErrorKind::NotFound,
+    ErrorKind::PermissionDenied,
+    ErrorKind::ConnectionRefused,
+    ErrorKind::ConnectionReset,
+    ErrorKind::ConnectionAborted,
+    ErrorKind::NotConnected,
+    ErrorKind::AddrInUse,
+    ErrorKind::AddrNotAvailable,
+    ErrorKind::BrokenPipe,
+    ErrorKind::AlreadyExists,
+    ErrorKind::WouldBlock,
+    ErrorKind::InvalidInput,
+    ErrorKind::InvalidData,
+    ErrorKind::TimedOut,
+    ErrorKind::WriteZero,
+    ErrorKind::Interrupted,
+    ErrorKind::Unsupported,
+    ErrorKind::UnexpectedEof,
+    ErrorKind::OutOfMemory,
+];
+
+/// Writes `error` into the report pipe. Called in the child right before exiting.
+pub fn send(writer: &mut PipeWriter, error: &Error) -> Result<(), io::Error> {
+    let mut buf = Buffer::new();
+    match *error {
+        Error::UserNamespacesUnsupported => buf.push(&[USER_NAMESPACES_UNSUPPORTED]),
+        Error::MaxUserNamespacesZero => buf.push(&[MAX_USER_NAMESPACES_ZERO]),
+        Error::Clone(ref e) => encode_io(&mut buf, CLONE, e),
+        Error::Privileges(ref e) => encode_io(&mut buf, PRIVILEGES, e),
+        Error::UidMap(ref e) => encode_io(&mut buf, UID_MAP, e),
+        Error::Namespace(ref e) => encode_io(&mut buf, NAMESPACE, e),
+        Error::Mount {
+            ref source,
+            ref dest,
+            ref error,
+        } => {
+            encode_io(&mut buf, MOUNT, error);
+            encode_bytes(&mut buf, source.as_os_str().as_bytes());
+            encode_bytes(&mut buf, dest.as_os_str().as_bytes());
+        }
+        Error::ProcfsDenied(ref path) => {
+            buf.push(&[PROCFS_DENIED]);
+            encode_bytes(&mut buf, path.as_os_str().as_bytes());
+        }
+        Error::Netlink(ref e) => encode_io(&mut buf, NETLINK, e),
+        Error::Seccomp(ref e) => encode_io(&mut buf, SECCOMP, e),
+        Error::Landlock(ref e) => encode_io(&mut buf, LANDLOCK, e),
+        Error::Cgroup(ref e) => encode_io(&mut buf, CGROUP, e),
+        Error::Rlimit(ref e) => encode_io(&mut buf, RLIMIT, e),
+        Error::Exec {
+            ref program,
+            ref error,
+        } => {
+            encode_io(&mut buf, EXEC, error);
+            encode_bytes(&mut buf, program.as_bytes());
+        }
+        Error::Io(ref e) => encode_io(&mut buf, IO, e),
+        Error::Mapping(MappingError(ref kind)) => {
+            let (kind, path) = match *kind {
+                MappingErrorKind::NotAbsolute(ref path) => (NOT_ABSOLUTE, path),
+                MappingErrorKind::NotNormalized(ref path) => (NOT_NORMALIZED, path),
+            };
+            buf.push(&[MAPPING, kind]);
+            encode_bytes(&mut buf, path.as_os_str().as_bytes());
+        }
+    }
+
+    writer.write_all(buf.as_slice())
+}
+
+/// Writes a deterministic `setting` the kernel couldn't honour into the report pipe. Called in the
+/// child, which goes on setting up the sandbox.
+pub fn send_unhonored(writer: &mut PipeWriter, setting: Unhonored) -> Result<(), io::Error> {
+    writer.write_all(&[UNHONORED, setting.to_raw()])
+}
+
+/// Reads the report of the child, blocking until it has either successfully executed the command
+/// or exited. Returns the unhonored settings, and the error unless the command was executed.
+pub fn recv(reader: &mut PipeReader) -> Result<(Vec<Unhonored>, Option<Error>), io::Error> {
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+
+    let mut decoder = Decoder(buf.as_slice());
+    let mut unhonored = Vec::new();
+    while !decoder.0.is_empty() {
+        match decoder.take(1)?[0] {
+            UNHONORED => {
+                let setting = Unhonored::from_raw(decoder.take(1)?[0]).ok_or_else(malformed)?;
+                unhonored.push(setting);
+            }
+            tag => return Ok((unhonored, Some(decode_error(&mut decoder, tag)?))),
+        }
+    }
+
+    Ok((unhonored, None))
+}
+
+fn decode_error(decoder: &mut Decoder, tag: u8) -> Result<Error, io::Error> {
+    let error = match tag {
+        USER_NAMESPACES_UNSUPPORTED => Error::UserNamespacesUnsupported,
+        MAX_USER_NAMESPACES_ZERO => Error::MaxUserNamespacesZero,
+        CLONE => Error::Clone(decoder.io()?),
+        PRIVILEGES => Error::Privileges(decoder.io()?),
+        UID_MAP => Error::UidMap(decoder.io()?),
+        NAMESPACE => Error::Namespace(decoder.io()?),
+        MOUNT => {
+            let error = decoder.io()?;
+            Error::Mount {
+                source: decoder.path()?,
+                dest: decoder.path()?,
+                error,
+            }
+        }
+        PROCFS_DENIED => Error::ProcfsDenied(decoder.path()?),
+        NETLINK => Error::Netlink(decoder.io()?),
+        SECCOMP => Error::Seccomp(decoder.io()?),
+        LANDLOCK => Error::Landlock(decoder.io()?),
+        CGROUP => Error::Cgroup(decoder.io()?),
+        RLIMIT => Error::Rlimit(decoder.io()?),
+        EXEC => {
+            let error = decoder.io()?;
+            Error::Exec {
+                program: decoder.path()?.into_os_string(),
+                error,
+            }
+        }
+        IO => Error::Io(decoder.io()?),
+        MAPPING => {
+            let kind = decoder.take(1)?[0];
+            let path = decoder.path()?;
+            let kind = match kind {
+                NOT_ABSOLUTE => MappingErrorKind::NotAbsolute(path),
+                NOT_NORMALIZED => MappingErrorKind::NotNormalized(path),
+                _ => return Err(malformed()),
+            };
+            Error::Mapping(MappingError(kind))
+        }
+        _ => return Err(malformed()),
+    };
+
+    Ok(error)
+}
+
+fn encode_io(buf: &mut Buffer, tag: u8, error: &io::Error) {
+    buf.push(&[tag]);
+    match error.raw_os_error() {
+        Some(errno) => buf.push(&errno.to_ne_bytes()),
+        None => {
+            let kind = KINDS.iter().position(|&k| k == error.kind()).unwrap_or(0);
+            buf.push(&0i32.to_ne_bytes());
+            buf.push(&[kind as u8]);
+
+            // Formatting into a slice stops once it is full, rather than allocating.
+            let mut message = [0; MAX_FIELD];
+            let mut rest = &mut message[..];
+            let _ = write!(rest, "{}", error);
+            let len = MAX_FIELD - rest.len();
+            encode_bytes(buf, &message[..len]);
+        }
+    }
+}
+
+fn encode_bytes(buf: &mut Buffer, bytes: &[u8]) {
+    let bytes = &bytes[..cmp::min(bytes.len(), MAX_FIELD)];
+    buf.push(&(bytes.len() as u32).to_ne_bytes());
+    buf.push(bytes);
+}
+
+/// A report being encoded, which is large enough for any error.
+struct Buffer {
+    bytes: [u8; MAX_REPORT],
+    len: usize,
+}
+
+impl Buffer {
+    fn new() -> Self {
+        Buffer {
+            bytes: [0; MAX_REPORT],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.bytes[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "Malformed failure report")
+}
+
+struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], io::Error> {
+        if self.0.len() < len {
+            return Err(malformed());
+        }
+
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn i32(&mut self) -> Result<i32, io::Error> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(i32::from_ne_bytes(bytes))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], io::Error> {
+        let len = self.i32()? as u32 as usize;
+        self.take(len)
+    }
+
+    fn path(&mut self) -> Result<PathBuf, io::Error> {
+        let bytes = self.bytes()?.to_vec();
+        Ok(PathBuf::from(OsString::from_vec(bytes)))
+    }
+
+    fn io(&mut self) -> Result<io::Error, io::Error> {
+        match self.i32()? {
+            0 => {
+                let kind = KINDS.get(self.take(1)?[0] as usize).ok_or_else(malformed)?;
+                let message = String::from_utf8_lossy(self.bytes()?).into_owned();
+                Ok(io::Error::new(*kind, message))
+            }
+            errno => Ok(io::Error::from_raw_os_error(errno)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends `error` through a pipe and decodes it again.
+    fn round_trip(error: &Error) -> Error {
+        let (mut reader, mut writer) = os_pipe::pipe().expect("failed to create pipe");
+        send(&mut writer, error).expect("failed to send report");
+        drop(writer);
+
+        let (unhonored, error) = recv(&mut reader).expect("failed to receive report");
+        assert!(unhonored.is_empty());
+        error.expect("report holds no error")
+    }
+
+    #[test]
+    fn io_error_kind() {
+        let error = io::Error::new(ErrorKind::Unsupported, "no cgroup2 filesystem");
+        match round_trip(&Error::Cgroup(error)) {
+            Error::Cgroup(e) => {
+                assert_eq!(e.kind(), ErrorKind::Unsupported);
+                assert_eq!(e.to_string(), "no cgroup2 filesystem");
+            }
+            other => panic!("decoded {:?}", other),
+        }
+    }
+
+    #[test]
+    fn os_error() {
+        let error = io::Error::from_raw_os_error(libc::ENOENT);
+        match round_trip(&Error::Mount {
+            source: PathBuf::from("/usr"),
+            dest: PathBuf::from("/new_root/usr"),
+            error,
+        }) {
+            Error::Mount {
+                source,
+                dest,
+                error,
+            } => {
+                assert_eq!(source, PathBuf::from("/usr"));
+                assert_eq!(dest, PathBuf::from("/new_root/usr"));
+                assert_eq!(error.raw_os_error(), Some(libc::ENOENT));
+            }
+            other => panic!("decoded {:?}", other),
+        }
+    }
+
+    #[test]
+    fn long_path() {
+        let path = PathBuf::from(format!("/{}", "a".repeat(2 * MAX_FIELD)));
+        match round_trip(&Error::ProcfsDenied(path.clone())) {
+            Error::ProcfsDenied(p) => {
+                assert_eq!(
+                    p.as_os_str().as_bytes(),
+                    &path.as_os_str().as_bytes()[..MAX_FIELD]
+                )
+            }
+            other => panic!("decoded {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mapping_error() {
+        let error = MappingError(MappingErrorKind::NotNormalized(PathBuf::from("/a/../b")));
+        match round_trip(&Error::Mapping(error.clone())) {
+            Error::Mapping(e) => assert_eq!(e, error),
+            other => panic!("decoded {:?}", other),
+        }
+    }
+}
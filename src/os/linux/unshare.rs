@@ -1,11 +1,11 @@
 use std::ffi::CString;
 use std::fs::{self, DirBuilder, OpenOptions};
-use std::io::{Error, ErrorKind, Read};
+use std::io::{self, ErrorKind, Read};
 use std::os::unix;
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
 use std::os::unix::io::AsRawFd;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{env, ptr};
 
 use libc::{c_char, c_int, c_ulong, c_void, pid_t};
@@ -13,42 +13,95 @@ use libmount::mountinfo;
 use log::{debug, trace};
 use openat::Dir;
 
-use super::{IS_PRIVILEGED, PROC_DIR};
-use crate::{util, Mapping, Sandbox};
+use super::Context;
+use crate::process::PidFd;
+use crate::{util, Error, Mapping, Sandbox};
 
-pub unsafe fn clone_process(config: &Sandbox) -> Result<pid_t, Error> {
-    fs::metadata("/proc/self/ns/user")
-        .map_err(|_| Error::new(ErrorKind::Other, "User namespaces are unsupported"))?;
+pub unsafe fn clone_process(config: &Sandbox) -> Result<(pid_t, Option<PidFd>), Error> {
+    let namespaces = &config.namespaces;
+    if namespaces.user {
+        fs::metadata("/proc/self/ns/user").map_err(|_| Error::UserNamespacesUnsupported)?;
 
-    if let Ok(max) = fs::read_to_string("/proc/sys/user/max_user_namespaces") {
-        let max_user_ns: i32 = max
-            .trim()
-            .parse()
-            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        if let Ok(max) = fs::read_to_string("/proc/sys/user/max_user_namespaces") {
+            let max_user_ns: i32 = max
+                .trim()
+                .parse()
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
 
-        if max_user_ns == 0 {
-            let msg = "Max user namespaces is set to 0";
-            return Err(Error::new(ErrorKind::Other, msg));
+            if max_user_ns == 0 {
+                return Err(Error::MaxUserNamespacesZero);
+            }
         }
     }
 
-    let mut unshare_flags = libc::SIGCHLD | libc::CLONE_NEWNS | libc::CLONE_NEWUSER;
-    if !config.allow_sysctl {
-        unshare_flags |= libc::CLONE_NEWPID;
+    if config.unshares_time() && fs::metadata("/proc/self/ns/time").is_err() {
+        let message = "time namespaces require Linux 5.6 or newer";
+        return Err(Error::Namespace(io::Error::new(
+            ErrorKind::Unsupported,
+            message,
+        )));
+    }
+
+    let has_mounts = !config.mappings.0.is_empty()
+        || !config.soft_links.is_empty()
+        || !config.directories.is_empty()
+        || config.cgroupfs;
+    if !namespaces.mount && has_mounts {
+        let message = "mappings, soft links, directories and cgroupfs require a mount namespace";
+        return Err(Error::Namespace(io::Error::new(
+            ErrorKind::InvalidInput,
+            message,
+        )));
+    }
+
+    if !namespaces.cgroup && config.cgroupfs {
+        let message = "mounting cgroupfs requires a cgroup namespace";
+        return Err(Error::Namespace(io::Error::new(
+            ErrorKind::InvalidInput,
+            message,
+        )));
     }
-    if !config.allow_network {
-        unshare_flags |= libc::CLONE_NEWNET;
+
+    let has_names =
+        config.hostname.is_some() || config.domainname.is_some() || config.deterministic;
+
+    // The time namespace can't be requested here, since `CLONE_NEWTIME` overlaps with the exit
+    // signal. It is unshared from inside the sandbox instead, and so is the cgroup namespace, which
+    // has to wait until the parent has moved us into a cgroup of our own.
+    let mut unshare_flags = libc::SIGCHLD;
+    let flags = [
+        (namespaces.user, libc::CLONE_NEWUSER),
+        (namespaces.mount, libc::CLONE_NEWNS),
+        (namespaces.pid, libc::CLONE_NEWPID),
+        (namespaces.net, libc::CLONE_NEWNET),
+        (namespaces.uts || has_names, libc::CLONE_NEWUTS),
+        (namespaces.ipc, libc::CLONE_NEWIPC),
+    ];
+    for &(enabled, flag) in &flags {
+        if enabled {
+            unshare_flags |= flag;
+        }
     }
 
-    util::catch_io_error(raw_clone(unshare_flags, ptr::null_mut()))
-        .map_err(|_| Error::new(ErrorKind::Other, "Unable to clone() process"))
+    // Also ask for a pidfd referring to the child, so it can be waited on and signaled without
+    // racing against PID reuse. Kernels older than 5.2 ignore the flag and leave `pidfd` untouched.
+    let mut pidfd: c_int = -1;
+    let flags = unshare_flags | libc::CLONE_PIDFD;
+    let pid = util::catch_io_error(raw_clone(flags, ptr::null_mut(), &mut pidfd))
+        .map_err(Error::Clone)?;
+
+    if pid != 0 && pidfd >= 0 {
+        Ok((pid, Some(PidFd::new(pidfd))))
+    } else {
+        Ok((pid, None))
+    }
 }
 
-unsafe fn raw_clone(flags: c_int, child_stack: *mut c_void) -> c_int {
-    libc::syscall(libc::SYS_clone, flags as c_ulong, child_stack) as c_int
+unsafe fn raw_clone(flags: c_int, child_stack: *mut c_void, pidfd: *mut c_int) -> c_int {
+    libc::syscall(libc::SYS_clone, flags as c_ulong, child_stack, pidfd) as c_int
 }
 
-pub unsafe fn setup_environment(config: &Sandbox) -> Result<(), Error> {
+pub unsafe fn setup_environment(ctx: &Context, config: &Sandbox) -> Result<(), Error> {
     // Need to do this before the chroot, but after we're the real uid.
     let mappings = config.mappings.resolve_symlinks()?;
 
@@ -61,7 +114,8 @@ pub unsafe fn setup_environment(config: &Sandbox) -> Result<(), Error> {
         ptr::null(),
         libc::MS_SLAVE | libc::MS_REC,
         ptr::null(),
-    ))?;
+    ))
+    .map_err(mount_error("/", "/"))?;
 
     // Create a tmpfs which we will use as / in the namespace.
     let base_path = CString::new("/tmp".as_bytes())?;
@@ -72,7 +126,8 @@ pub unsafe fn setup_environment(config: &Sandbox) -> Result<(), Error> {
         tmpfs.as_ptr(),
         libc::MS_NODEV | libc::MS_NOSUID,
         ptr::null(),
-    ))?;
+    ))
+    .map_err(mount_error("tmpfs", "/tmp"))?;
 
     // Chdir to the new root tmpfs mount. This will be the CWD during the entire setup. Access old
     // or new root via "old_root" and "new_root".
@@ -94,21 +149,23 @@ pub unsafe fn setup_environment(config: &Sandbox) -> Result<(), Error> {
         ptr::null(),
         libc::MS_MGC_VAL | libc::MS_BIND | libc::MS_REC,
         ptr::null(),
-    ))?;
+    ))
+    .map_err(mount_error("/tmp/new_root", "/tmp/new_root"))?;
 
     let old_root = "old_root";
     DirBuilder::new().mode(0o0755).create(&old_root)?;
 
     // NB: This is our first pivot to `old_root`!
     let old_root = CString::new(old_root)?;
-    util::catch_io_error(pivot_root(base_path.as_ptr(), old_root.as_ptr()))?;
+    util::catch_io_error(pivot_root(base_path.as_ptr(), old_root.as_ptr()))
+        .map_err(mount_error("/tmp", "/tmp/old_root"))?;
     env::set_current_dir("/")?;
 
-    if IS_PRIVILEGED {
+    if ctx.is_privileged {
         // TODO: Need to fork process and run the code below using an unprivileged socket.
-        setup_new_root(&config, mappings.as_slice())?;
+        setup_new_root(ctx, &config, mappings.as_slice())?;
     } else {
-        setup_new_root(&config, mappings.as_slice())?;
+        setup_new_root(ctx, &config, mappings.as_slice())?;
     }
 
     // The old root better be rprivate or we will send unmount events to the parent namespace.
@@ -118,10 +175,12 @@ pub unsafe fn setup_environment(config: &Sandbox) -> Result<(), Error> {
         ptr::null(),
         libc::MS_REC | libc::MS_PRIVATE,
         ptr::null(),
-    ))?;
+    ))
+    .map_err(mount_error("/old_root", "/old_root"))?;
 
     // Detach the old root.
-    util::catch_io_error(libc::umount2(old_root.as_ptr(), libc::MNT_DETACH))?;
+    util::catch_io_error(libc::umount2(old_root.as_ptr(), libc::MNT_DETACH))
+        .map_err(mount_error("/old_root", "/old_root"))?;
 
     // NB: This is our second pivot!
     //
@@ -140,9 +199,11 @@ pub unsafe fn setup_environment(config: &Sandbox) -> Result<(), Error> {
     // https://github.com/opencontainers/runc/blob/master/libcontainer/rootfs_linux.go#L671
     // https://github.com/lxc/lxc/blob/master/src/lxc/conf.c#L1121
     let dot = CString::new(".".as_bytes())?;
-    util::catch_io_error(pivot_root(dot.as_ptr(), dot.as_ptr()))?;
+    util::catch_io_error(pivot_root(dot.as_ptr(), dot.as_ptr()))
+        .map_err(mount_error("/new_root", "/new_root"))?;
     util::catch_io_error(libc::fchdir(old_root_dir.as_raw_fd()))?;
-    util::catch_io_error(libc::umount2(dot.as_ptr(), libc::MNT_DETACH))?;
+    util::catch_io_error(libc::umount2(dot.as_ptr(), libc::MNT_DETACH))
+        .map_err(mount_error("/", "/"))?;
     env::set_current_dir("/")?;
     env::set_var("PWD", "/");
 
@@ -161,23 +222,41 @@ pub unsafe fn setup_environment(config: &Sandbox) -> Result<(), Error> {
     Ok(())
 }
 
+fn mount_error<P, Q>(source: P, dest: Q) -> impl FnOnce(io::Error) -> Error
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let source = source.as_ref().to_path_buf();
+    let dest = dest.as_ref().to_path_buf();
+    move |error| Error::Mount {
+        source,
+        dest,
+        error,
+    }
+}
+
 unsafe fn pivot_root(new_root: *const c_char, put_old: *const c_char) -> c_int {
     libc::syscall(libc::SYS_pivot_root, new_root, put_old) as c_int
 }
 
-unsafe fn setup_new_root(config: &Sandbox, mappings: &[Mapping]) -> Result<(), Error> {
+unsafe fn setup_new_root(
+    ctx: &Context,
+    config: &Sandbox,
+    mappings: &[Mapping],
+) -> Result<(), Error> {
     for mapping in mappings {
         let source = mapping
             .host
             .strip_prefix("/")
             .map(|p| Path::new("/old_root").join(p))
-            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+            .map_err(|e| io::Error::new(ErrorKind::Other, e))?;
 
         let dest = mapping
             .sandbox
             .strip_prefix("/")
             .map(|p| Path::new("/new_root").join(p))
-            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+            .map_err(|e| io::Error::new(ErrorKind::Other, e))?;
 
         if source.is_dir() {
             DirBuilder::new()
@@ -200,19 +279,48 @@ unsafe fn setup_new_root(config: &Sandbox, mappings: &[Mapping]) -> Result<(), E
         }
 
         bind_mount(
+            ctx,
             &source,
             &dest,
             mapping.writable,
+            mapping.executable,
             config.allow_devices,
             config.allow_sysctl,
         )?;
     }
 
+    // A private IPC namespace comes with its own POSIX shared memory and message queues, which
+    // need filesystems of their own to be usable. Since the namespace is unshared by default,
+    // `/dev/shm` is only mounted if asked for or already present, and neither mount fails the
+    // spawn if a read-only mapping of `/dev` lacks its mount point.
+    if config.namespaces.ipc {
+        let flags = libc::MS_NOSUID | libc::MS_NODEV;
+        let shm = "/new_root/dev/shm";
+        if (config.shm_size.is_some() || Path::new(shm).is_dir()) && optional_mount_point(shm)? {
+            let shm_options = match config.shm_size {
+                Some(size) => format!("mode=1777,size={}", size),
+                None => "mode=1777".to_owned(),
+            };
+            mount_fs("tmpfs", shm, flags, Some(&shm_options))?;
+        }
+
+        let mqueue = "/new_root/dev/mqueue";
+        if optional_mount_point(mqueue)? {
+            mount_fs("mqueue", mqueue, flags | libc::MS_NOEXEC, None)?;
+        }
+    }
+
+    // This only exposes the hierarchy beneath the root of our cgroup namespace.
+    if config.cgroupfs {
+        let flags = libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC;
+        mount_fs("cgroup2", "/new_root/sys/fs/cgroup", flags, None)?;
+    }
+
     for (source, dest) in &config.soft_links {
         let dest = dest
             .strip_prefix("/")
             .map(|p| Path::new("/new_root").join(p))
-            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+            .map_err(|e| io::Error::new(ErrorKind::Other, e))?;
 
         if let Some(parent) = dest.parent() {
             DirBuilder::new()
@@ -229,7 +337,7 @@ unsafe fn setup_new_root(config: &Sandbox, mappings: &[Mapping]) -> Result<(), E
         let dir = dir
             .strip_prefix("/")
             .map(|p| Path::new("/new_root").join(p))
-            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+            .map_err(|e| io::Error::new(ErrorKind::Other, e))?;
 
         debug!("creating new directory {:?}", dir);
         DirBuilder::new().mode(0o755).recursive(true).create(dir)?;
@@ -238,10 +346,49 @@ unsafe fn setup_new_root(config: &Sandbox, mappings: &[Mapping]) -> Result<(), E
     Ok(())
 }
 
+/// Creates the mount point `dest` if needed, returning whether it exists.
+///
+/// Unlike `mount_fs()`, this doesn't fail if `dest` lies beneath a read-only mapping.
+fn optional_mount_point(dest: &str) -> Result<bool, io::Error> {
+    match DirBuilder::new().mode(0o755).recursive(true).create(dest) {
+        Ok(()) => Ok(true),
+        Err(ref e) if e.raw_os_error() == Some(libc::EROFS) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Mounts a fresh instance of the filesystem `fstype` at `dest`, creating the latter if needed.
+unsafe fn mount_fs(
+    fstype: &str,
+    dest: &str,
+    flags: c_ulong,
+    options: Option<&str>,
+) -> Result<(), Error> {
+    DirBuilder::new().mode(0o755).recursive(true).create(dest)?;
+
+    let fstype_c = CString::new(fstype)?;
+    let dest_c = CString::new(dest)?;
+    let options = options.map(CString::new).transpose()?;
+    util::catch_io_error(libc::mount(
+        fstype_c.as_ptr(),
+        dest_c.as_ptr(),
+        fstype_c.as_ptr(),
+        flags,
+        options
+            .as_ref()
+            .map_or(ptr::null(), |o| o.as_ptr() as *const c_void),
+    ))
+    .map_err(mount_error(fstype, strip_root(Path::new(dest))))?;
+
+    Ok(())
+}
+
 fn bind_mount(
+    ctx: &Context,
     source: &Path,
     dest: &Path,
     writable: bool,
+    executable: bool,
     allow_devices: bool,
     allow_sysctl: bool,
 ) -> Result<(), Error> {
@@ -255,14 +402,13 @@ fn bind_mount(
             libc::MS_BIND | libc::MS_REC,
             ptr::null(),
         )
-    })?;
+    })
+    .map_err(mount_error(strip_root(source), strip_root(dest)))?;
 
     trace!("mounted successfully");
 
-    let mount_info = unsafe {
-        let proc = PROC_DIR
-            .as_ref()
-            .ok_or_else(|| Error::new(ErrorKind::Other, "Expected /proc to be open"))?;
+    let mount_info = {
+        let proc = &ctx.proc_dir;
         let proc_self = proc.read_link("self")?;
         let mut mount_info = proc.open_file(&proc_self.join("mountinfo"))?;
 
@@ -272,7 +418,7 @@ fn bind_mount(
     };
 
     let mut mount_points: Vec<_> = mountinfo::Parser::new(mount_info.as_slice())
-        .map(|mount| mount.map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string())))
+        .map(|mount| mount.map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string())))
         .collect::<Result<_, _>>()?;
 
     mount_points.retain(|mount| Path::new(&mount.mount_point).starts_with(&dest));
@@ -282,8 +428,7 @@ fn bind_mount(
 
     let root_mount_point = mount_points.remove(0);
     if root_mount_point.fstype.to_string_lossy() == "proc" && !allow_sysctl {
-        let msg = "Mounting procfs is not permitted";
-        return Err(Error::new(ErrorKind::PermissionDenied, msg));
+        return Err(Error::ProcfsDenied(strip_root(dest)));
     }
 
     assert_eq!(root_mount_point.mount_point, dest);
@@ -295,11 +440,15 @@ fn bind_mount(
     if !writable {
         flags |= libc::MS_RDONLY;
     }
+    if !executable {
+        flags |= libc::MS_NOEXEC;
+    }
 
     trace!("MS_NODEV: {}, MS_RDONLY: {}", !allow_devices, !writable);
     trace!("new flags: {}, current flags: {}", flags, current_flags);
     if flags != current_flags {
         trace!("remounting {:?}", dest);
+        let remount_error = mount_error("none", strip_root(dest));
         let none = CString::new("none".as_bytes())?;
         let dest = CString::new(dest.as_os_str().as_bytes())?;
         util::catch_io_error(unsafe {
@@ -310,7 +459,8 @@ fn bind_mount(
                 libc::MS_BIND | libc::MS_REMOUNT | flags,
                 ptr::null(),
             )
-        })?;
+        })
+        .map_err(remount_error)?;
         trace!("successfully remounted {:?}", dest);
     }
 
@@ -319,8 +469,9 @@ fn bind_mount(
     // the flags to mounts which are later propagated into this namespace.
     for mount in mount_points {
         if mount.fstype.to_string_lossy() == "proc" && !allow_sysctl {
-            let msg = "Mounting procfs is not permitted";
-            return Err(Error::new(ErrorKind::PermissionDenied, msg));
+            return Err(Error::ProcfsDenied(strip_root(Path::new(
+                &mount.mount_point,
+            ))));
         }
 
         let current_flags = mount.get_flags();
@@ -331,6 +482,9 @@ fn bind_mount(
         if !writable {
             flags |= libc::MS_RDONLY;
         }
+        if !executable {
+            flags |= libc::MS_NOEXEC;
+        }
 
         trace!("MS_NODEV: {}, MS_RDONLY: {}", !allow_devices, !writable);
         trace!("new: {}, current: {}", flags, current_flags);
@@ -350,7 +504,8 @@ fn bind_mount(
 
             if let Err(err) = result {
                 if err.raw_os_error() != Some(libc::EACCES) {
-                    return Err(err);
+                    let dest = strip_root(Path::new(&mount.mount_point));
+                    return Err(mount_error("none", dest)(err));
                 }
             }
 
@@ -361,3 +516,12 @@ fn bind_mount(
     trace!("recursive bind mount complete");
     Ok(())
 }
+
+/// Strips the `/old_root` or `/new_root` prefix used during setup from `path`, yielding the path as
+/// seen from the host or from inside the sandbox, respectively.
+fn strip_root(path: &Path) -> PathBuf {
+    path.strip_prefix("/old_root")
+        .or_else(|_| path.strip_prefix("/new_root"))
+        .map(|p| Path::new("/").join(p))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
@@ -2,7 +2,7 @@
 
 use std::borrow::Cow;
 use std::ffi::{CStr, CString};
-use std::io::{Error, ErrorKind, Read, Write};
+use std::io::{self, ErrorKind, Read, Write};
 use std::net::Shutdown;
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::net::UnixStream;
@@ -17,12 +17,8 @@ use log::{debug, trace};
 use once_cell::sync::Lazy;
 
 use self::sandboxfs::Sandboxfs;
-use crate::process::Child;
-#[cfg(feature = "piped")]
-use crate::process::ChildStderr;
-#[cfg(any(feature = "piped", feature = "piped-merged"))]
-use crate::process::{ChildStdin, ChildStdout};
-use crate::{util, Sandbox};
+use crate::process::{self, Child};
+use crate::{rlimit, util, Error, Sandbox, TerminalIsolation};
 
 mod sandboxfs;
 
@@ -47,22 +43,13 @@ pub fn create_sandbox(config: &Sandbox, command: &mut Command) -> Result<Child,
     };
 
     let (mut tx, mut rx) = UnixStream::pair()?;
-    #[cfg(any(feature = "piped", feature = "piped-merged"))]
-    let (stdin_r, stdin_w) = os_pipe::pipe()?;
-    #[cfg(any(feature = "piped", feature = "piped-merged"))]
-    let (stdout_r, stdout_w) = os_pipe::pipe()?;
-    #[cfg(feature = "piped")]
-    let (stderr_r, stderr_w) = os_pipe::pipe()?;
+    let (child_pipes, parent_pipes) =
+        process::create_pipes(&config.stdin, &config.stdout, &config.stderr)?;
 
     let sandbox_pid = util::catch_io_error(unsafe { libc::fork() })?;
     if sandbox_pid == 0 {
         drop(tx);
-        #[cfg(any(feature = "piped", feature = "piped-merged"))]
-        drop(stdin_w);
-        #[cfg(any(feature = "piped", feature = "piped-merged"))]
-        drop(stdout_r);
-        #[cfg(feature = "piped")]
-        drop(stderr_r);
+        drop(parent_pipes);
 
         let mount_point = temp_dir.into_path().join("mnt");
 
@@ -105,7 +92,7 @@ pub fn create_sandbox(config: &Sandbox, command: &mut Command) -> Result<Child,
             profile.push("(allow file-ioctl (subpath \"/\"))\n");
         }
 
-        if config.allow_network {
+        if !config.namespaces.net {
             profile.push("(allow network* (local ip) (local tcp) (local udp))\n");
             profile.push("(allow network* (remote ip) (remote tcp) (remote udp))\n");
             profile.push("(allow network* (remote unix-socket))\n");
@@ -122,9 +109,9 @@ pub fn create_sandbox(config: &Sandbox, command: &mut Command) -> Result<Child,
         let c_str = profile.to_cstring();
         if unsafe { sandbox_init(c_str.as_ptr(), 0, &mut error_buf) } == -1 {
             let raw_error = unsafe { CStr::from_ptr(error_buf) };
-            let error = Error::new(ErrorKind::Other, raw_error.to_string_lossy());
+            let error = io::Error::new(ErrorKind::Other, raw_error.to_string_lossy());
             unsafe { sandbox_free_error(error_buf) };
-            Err(error)
+            Err(error.into())
         } else {
             if old_cwd.exists() {
                 env::set_current_dir(&old_cwd)?;
@@ -135,27 +122,24 @@ pub fn create_sandbox(config: &Sandbox, command: &mut Command) -> Result<Child,
                 }
             }
 
-            // FIXME: Commands are currently statically forced to run in either inherit or piped
-            // mode until the `std::command::Command` builder offers some way to extract its fields
-            // and inspect them at run-time.
-            #[cfg(feature = "piped")]
-            let error = command
-                .stdin(stdin_r)
-                .stdout(stdout_w)
-                .stderr(stderr_w)
-                .exec();
-            #[cfg(feature = "piped-merged")]
-            let error = command
-                .stdin(stdin_r)
-                .stdout(stdout_w.try_clone()?)
-                .stderr(stdout_w)
-                .exec();
-            #[cfg(not(any(feature = "piped", feature = "piped-merged")))]
+            // Mitigate the CVE-2017-5226 sandbox escape. Seccomp is unavailable on macOS.
+            if config.terminal_isolation == TerminalIsolation::NewSession {
+                util::catch_io_error(unsafe { libc::setsid() })?;
+            }
+
+            rlimit::apply(&config.rlimits).map_err(Error::Rlimit)?;
+
+            child_pipes.apply(command);
             let error = command.exec();
 
-            Err(error)
+            Err(Error::Exec {
+                program: command.get_program().to_owned(),
+                error,
+            })
         }
     } else {
+        drop(child_pipes);
+
         let sandboxfs = Sandboxfs::new(temp_dir, &config)?;
         let handle = thread::Builder::new()
             .name("sandboxfs-monitor".into())
@@ -178,18 +162,11 @@ pub fn create_sandbox(config: &Sandbox, command: &mut Command) -> Result<Child,
 
         HANDLES.lock().unwrap().push(Some(handle));
 
-        #[cfg(any(feature = "piped", feature = "piped-merged"))]
-        let stdin = ChildStdin(stdin_w);
-        #[cfg(feature = "piped")]
-        let stdout = ChildStdout(stdout_r);
-        #[cfg(feature = "piped-merged")]
-        let stdout = ChildStdout(stdout_r);
-        #[cfg(feature = "piped")]
-        let stderr = ChildStderr(stderr_r);
-        #[cfg(feature = "piped-merged")]
-        let stderr = None;
-        #[cfg(not(any(feature = "piped", feature = "piped-merged")))]
-        let (stdin, stdout, stderr) = (None, None, None);
+        let process::ParentPipes {
+            stdin,
+            stdout,
+            stderr,
+        } = parent_pipes;
 
         Ok(Child::from_parts(stdin, stdout, stderr, sandbox_pid))
     }
@@ -1,24 +1,50 @@
-// TODO: There should be an async version of this module when tokio 0.2 introduces a [blocking API]
-// where long-running IO calls can be passed to a threadpool and converted into a `Future`.
-//
-// [blocking API]: https://github.com/tokio-rs/tokio/issues/588
-
+use std::fs::{File, OpenOptions};
 use std::io::{self, Error, ErrorKind, Read, Write};
-use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
-use std::os::unix::process::ExitStatusExt;
-use std::process::{ExitStatus, Output};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, ExitStatus, Output};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use libc::{c_int, pid_t};
 use os_pipe::{PipeReader, PipeWriter};
 
+#[cfg(target_os = "linux")]
+use crate::cgroup::Cgroup;
+#[cfg(target_os = "linux")]
+use crate::deterministic::Unhonored;
+#[cfg(target_os = "linux")]
+use crate::landlock;
+use crate::rlimit::Resource;
 use crate::util;
 
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+pub mod tokio;
+
 #[derive(Debug)]
 pub struct Child {
     pub stdin: Option<ChildStdin>,
     pub stdout: Option<ChildStdout>,
     pub stderr: Option<ChildStderr>,
     pid: pid_t,
+    #[cfg(target_os = "linux")]
+    pidfd: Option<PidFd>,
+    #[cfg(target_os = "linux")]
+    pid_namespace: bool,
+    #[cfg(target_os = "linux")]
+    init: bool,
+    #[cfg(target_os = "linux")]
+    command_status_pipe: Option<PipeReader>,
+    #[cfg(target_os = "linux")]
+    command_status: Option<ExitStatus>,
+    #[cfg(target_os = "linux")]
+    landlock: Option<landlock::Status>,
+    #[cfg(target_os = "linux")]
+    cgroup: Option<Cgroup>,
+    #[cfg(target_os = "linux")]
+    unhonored: Vec<Unhonored>,
     status: Option<ExitStatus>,
 }
 
@@ -34,9 +60,135 @@ impl Child {
             stdout: stdout.into(),
             stderr: stderr.into(),
             pid,
+            #[cfg(target_os = "linux")]
+            pidfd: None,
+            #[cfg(target_os = "linux")]
+            pid_namespace: false,
+            #[cfg(target_os = "linux")]
+            init: false,
+            #[cfg(target_os = "linux")]
+            command_status_pipe: None,
+            #[cfg(target_os = "linux")]
+            command_status: None,
+            #[cfg(target_os = "linux")]
+            landlock: None,
+            #[cfg(target_os = "linux")]
+            cgroup: None,
+            #[cfg(target_os = "linux")]
+            unhonored: Vec::new(),
             status: None,
         }
     }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn with_pidfd(mut self, pidfd: Option<PidFd>) -> Self {
+        self.pidfd = pidfd;
+        self
+    }
+
+    /// Marks the process as the init of its own PID namespace, so signals reach all its members.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn with_pid_namespace(mut self, enabled: bool) -> Self {
+        self.pid_namespace = enabled;
+        self
+    }
+
+    /// Marks the process as an init, which writes the raw wait status of the command into the
+    /// pipe read by `status` before exiting.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn with_init(mut self, status: Option<PipeReader>) -> Self {
+        self.init = status.is_some();
+        self.command_status_pipe = status;
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn with_landlock(mut self, status: Option<landlock::Status>) -> Self {
+        self.landlock = status;
+        self
+    }
+
+    /// Hands over the cgroup of the process, to be removed once the process has been reaped.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn with_cgroup(mut self, cgroup: Option<Cgroup>) -> Self {
+        self.cgroup = cgroup;
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn with_unhonored(mut self, unhonored: Vec<Unhonored>) -> Self {
+        self.unhonored = unhonored;
+        self
+    }
+
+    /// Records the raw wait status of the reaped process, cleaning up after it.
+    fn set_status(&mut self, status: c_int) -> ExitStatus {
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(cgroup) = self.cgroup.take() {
+                cgroup.remove();
+            }
+
+            // Init has exited, so this doesn't block. Nothing is read if it was killed.
+            if let Some(mut pipe) = self.command_status_pipe.take() {
+                let mut raw = [0; mem::size_of::<c_int>()];
+                if pipe.read_exact(&mut raw).is_ok() {
+                    self.command_status = Some(ExitStatus::from_raw(c_int::from_ne_bytes(raw)));
+                }
+            }
+        }
+
+        let status = ExitStatus::from_raw(status);
+        self.status = Some(status);
+        status
+    }
+
+    /// Waits for the process to exit, returning its raw wait status, or `None` if `WNOHANG` was
+    /// given and it is still running.
+    fn wait_status(&self, options: c_int) -> Result<Option<c_int>, Error> {
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(ref pidfd) = self.pidfd {
+                match pidfd.wait(options) {
+                    // `P_PIDFD` is only supported since Linux 5.4, one release after `CLONE_PIDFD`.
+                    Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) => {}
+                    other => return other,
+                }
+            }
+        }
+
+        let mut status = 0 as c_int;
+        let pid = util::catch_io_error_repeat(|| unsafe {
+            libc::waitpid(self.pid, &mut status, options)
+        })?;
+
+        Ok(if pid == 0 { None } else { Some(status) })
+    }
+
+    /// Blocks until the process may have exited, or until `timeout` has elapsed.
+    fn sleep_until_exit(&self, timeout: Duration) -> Result<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(ref pidfd) = self.pidfd {
+                let mut pollfd = libc::pollfd {
+                    fd: pidfd.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+
+                // Round up, so the last fraction of a millisecond isn't spent spinning on zero.
+                let millis = (timeout.as_micros() + 999) / 1000;
+                let millis = millis.min(c_int::max_value() as u128) as c_int;
+                return match util::catch_io_error(unsafe { libc::poll(&mut pollfd, 1, millis) }) {
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => Ok(()),
+                    other => other.map(|_| ()),
+                };
+            }
+        }
+
+        thread::sleep(timeout.min(Duration::from_millis(10)));
+        Ok(())
+    }
 }
 
 impl Child {
@@ -44,6 +196,41 @@ impl Child {
         self.pid as u32
     }
 
+    /// Returns the pidfd referring to the process, or `None` if the kernel doesn't support them.
+    #[cfg(target_os = "linux")]
+    pub fn pidfd(&self) -> Option<&PidFd> {
+        self.pidfd.as_ref()
+    }
+
+    /// Returns the Landlock restrictions applied to the process, or `None` if the kernel couldn't
+    /// enforce any of them.
+    #[cfg(target_os = "linux")]
+    pub fn landlock(&self) -> Option<&landlock::Status> {
+        self.landlock.as_ref()
+    }
+
+    /// Returns the settings of `Sandbox::deterministic()` which the kernel couldn't honour, if any.
+    #[cfg(target_os = "linux")]
+    pub fn unhonored(&self) -> &[Unhonored] {
+        &self.unhonored
+    }
+
+    /// Returns the resource limit the command was killed for exceeding, or `None` if it is still
+    /// running or exited otherwise.
+    ///
+    /// Only the limits enforced with a signal can be recognized, namely `Cpu` with `SIGXCPU` and
+    /// `Fsize` with `SIGXFSZ`. Exceeding the hard `Cpu` limit ends in `SIGKILL` instead, and the
+    /// other limits make system calls fail with an error the command has to handle itself. With
+    /// `Sandbox::init()`, this looks at how the command itself exited, as reported by init.
+    pub fn exceeded_rlimit(&self) -> Option<Resource> {
+        let status = self.status?;
+
+        #[cfg(target_os = "linux")]
+        let status = self.command_status.unwrap_or(status);
+
+        status.signal().and_then(Resource::from_signal)
+    }
+
     pub fn wait(&mut self) -> Result<ExitStatus, Error> {
         drop(self.stdin.take());
 
@@ -51,11 +238,10 @@ impl Child {
             return Ok(status);
         }
 
-        let mut status = 0 as c_int;
-        util::catch_io_error_repeat(|| unsafe { libc::waitpid(self.pid, &mut status, 0) })?;
-
-        self.status = Some(ExitStatus::from_raw(status));
-        Ok(ExitStatus::from_raw(status))
+        let status = self
+            .wait_status(0)?
+            .expect("blocking wait returned no status");
+        Ok(self.set_status(status))
     }
 
     pub fn try_wait(&mut self) -> Result<Option<ExitStatus>, Error> {
@@ -63,61 +249,495 @@ impl Child {
             return Ok(Some(status));
         }
 
-        let mut status = 0 as c_int;
-        let pid = util::catch_io_error_repeat(|| unsafe {
-            libc::waitpid(self.pid, &mut status, libc::WNOHANG)
-        })?;
-
-        if pid == 0 {
-            Ok(None)
-        } else {
-            self.status = Some(ExitStatus::from_raw(status));
-            Ok(Some(ExitStatus::from_raw(status)))
+        match self.wait_status(libc::WNOHANG)? {
+            None => Ok(None),
+            Some(status) => Ok(Some(self.set_status(status))),
         }
     }
 
-    pub fn wait_with_output(&mut self) -> Result<Output, Error> {
+    /// Waits for the process to exit for at most `timeout`, returning `None` if it is still
+    /// running afterwards.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<Option<ExitStatus>, Error> {
         drop(self.stdin.take());
 
-        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
-        match (self.stdout.take(), self.stderr.take()) {
-            (None, None) => {}
-            (Some(mut out), None) => {
-                let res = out.read_to_end(&mut stdout);
-                res.unwrap();
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(status) = self.try_wait()? {
+                return Ok(Some(status));
             }
-            (None, Some(mut err)) => {
-                let res = err.read_to_end(&mut stderr);
-                res.unwrap();
-            }
-            (Some(mut out), Some(mut err)) => {
-                let res = out.read_to_end(&mut stdout);
-                res.unwrap();
-                let res = err.read_to_end(&mut stderr);
-                res.unwrap();
-                // FIXME: Should implement some kind of simultaneous non-blocking read of both
-                // pipes to ensure they don't block on each other. This seems to work for the short
-                // term, though. See this source file for more details:
-                // https://github.com/rust-lang/rust/blob/fae75cd216c481de048e4951697c8f8525669c65/src/libstd/sys/unix/pipe.rs#L80-L129
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
             }
+
+            self.sleep_until_exit(deadline - now)?;
         }
+    }
 
+    pub fn wait_with_output(&mut self) -> Result<Output, Error> {
+        drop(self.stdin.take());
+
+        let (stdout, stderr) = read2(self.stdout.take(), self.stderr.take(), None)?;
         let status = self.wait()?;
         Ok(Output {
             status,
-            stdout,
-            stderr,
+            stdout: stdout.bytes,
+            stderr: stderr.bytes,
+        })
+    }
+
+    /// Like `wait_with_output()`, but keeps at most `limit` bytes of each stream.
+    ///
+    /// Anything past the limit is still read from the pipes, so the child never blocks on a full
+    /// pipe, but it is discarded and the stream is flagged as truncated.
+    pub fn wait_with_output_capped(&mut self, limit: usize) -> Result<CappedOutput, Error> {
+        drop(self.stdin.take());
+
+        let (stdout, stderr) = read2(self.stdout.take(), self.stderr.take(), Some(limit))?;
+        let status = self.wait()?;
+        Ok(CappedOutput {
+            status,
+            stdout: stdout.bytes,
+            stderr: stderr.bytes,
+            stdout_truncated: stdout.truncated,
+            stderr_truncated: stderr.truncated,
         })
     }
 
+    /// Sends `SIGKILL` to the process.
+    ///
+    /// If the process is the init of its own PID namespace, the kernel kills every other process
+    /// in the namespace along with it.
     pub fn kill(&mut self) -> Result<(), Error> {
         if self.status.is_some() {
             let msg = "invalid argument: can't kill an exited process";
             Err(Error::new(ErrorKind::InvalidInput, msg))
         } else {
-            util::catch_io_error(unsafe { libc::kill(self.pid, libc::SIGKILL) }).map(|_| ())
+            self.send_signal(libc::SIGKILL)
+        }
+    }
+
+    /// Sends `signal` to the process.
+    ///
+    /// If the process is the init of its own PID namespace, the signal is delivered to every
+    /// other process in the namespace as well.
+    pub fn signal(&mut self, signal: i32) -> Result<(), Error> {
+        if self.status.is_some() {
+            let msg = "invalid argument: can't signal an exited process";
+            return Err(Error::new(ErrorKind::InvalidInput, msg));
+        }
+
+        // An init of ours passes the signal on to the rest of the namespace by itself.
+        #[cfg(target_os = "linux")]
+        {
+            if self.pid_namespace && !self.init {
+                signal_pid_namespace(self.pid, signal)?;
+            }
+        }
+
+        self.send_signal(signal)
+    }
+
+    /// Sends `SIGTERM` to the process, then kills it if it hasn't exited within `grace`.
+    pub fn terminate(&mut self, grace: Duration) -> Result<ExitStatus, Error> {
+        if let Some(status) = self.status {
+            return Ok(status);
+        }
+
+        self.signal(libc::SIGTERM)?;
+        if let Some(status) = self.wait_timeout(grace)? {
+            return Ok(status);
+        }
+
+        self.kill()?;
+        self.wait()
+    }
+
+    fn send_signal(&self, signal: c_int) -> Result<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(ref pidfd) = self.pidfd {
+                return pidfd.send_signal(signal);
+            }
+        }
+
+        util::catch_io_error(unsafe { libc::kill(self.pid, signal) }).map(|_| ())
+    }
+}
+
+/// Sends `signal` to every process sharing the PID namespace of `init`, except `init` itself.
+///
+/// Members are found by comparing `/proc/[pid]/ns/pid` against that of `init`. Each process is
+/// pinned with a pidfd before it is inspected, so a pid reused in the meantime is never signalled.
+/// Processes which exit or can't be inspected while scanning are skipped. Without `pidfd_open()`,
+/// which needs Linux 5.3, none of them are signalled.
+#[cfg(target_os = "linux")]
+fn signal_pid_namespace(init: pid_t, signal: c_int) -> Result<(), Error> {
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+
+    let namespace = fs::metadata(format!("/proc/{}/ns/pid", init))?;
+    for entry in fs::read_dir("/proc")? {
+        let pid = match entry?
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<pid_t>().ok())
+        {
+            Some(pid) if pid != init => pid,
+            _ => continue,
+        };
+
+        let pidfd = match PidFd::open(pid) {
+            Ok(pidfd) => pidfd,
+            Err(_) => continue,
+        };
+
+        // If the process has exited since, the pidfd refers to nothing and the signal goes nowhere,
+        // no matter which process the pid belongs to by now.
+        match fs::metadata(format!("/proc/{}/ns/pid", pid)) {
+            Ok(ns) if ns.dev() == namespace.dev() && ns.ino() == namespace.ino() => {
+                let _ = pidfd.send_signal(signal);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// A file descriptor referring to a sandboxed process.
+///
+/// Unlike a PID, it can't end up referring to an unrelated process once the sandboxed process has
+/// exited and been reaped. It becomes readable when the process exits, so it can be registered
+/// with an external event loop.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct PidFd(File);
+
+#[cfg(target_os = "linux")]
+impl PidFd {
+    pub(crate) unsafe fn new(fd: RawFd) -> Self {
+        PidFd(File::from_raw_fd(fd))
+    }
+
+    fn open(pid: pid_t) -> Result<Self, Error> {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        util::catch_io_error(fd as c_int).map(|fd| unsafe { PidFd::new(fd) })
+    }
+
+    fn send_signal(&self, signal: c_int) -> Result<(), Error> {
+        let null = std::ptr::null::<libc::siginfo_t>();
+        let fd = self.0.as_raw_fd();
+        let res = unsafe { libc::syscall(libc::SYS_pidfd_send_signal, fd, signal, null, 0) };
+        util::catch_io_error(res as c_int).map(|_| ())
+    }
+
+    fn wait(&self, options: c_int) -> Result<Option<c_int>, Error> {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let fd = self.0.as_raw_fd() as libc::id_t;
+        util::catch_io_error_repeat(|| unsafe {
+            libc::waitid(libc::P_PIDFD, fd, &mut info, libc::WEXITED | options)
+        })?;
+
+        // With `WNOHANG`, `si_pid` is left zeroed if the process is still running.
+        if unsafe { info.si_pid() } == 0 {
+            return Ok(None);
+        }
+
+        // Reassemble the status `waitpid()` would have returned, which is what `ExitStatus` wraps.
+        let status = unsafe { info.si_status() };
+        match info.si_code {
+            libc::CLD_EXITED => Ok(Some((status & 0xff) << 8)),
+            libc::CLD_KILLED => Ok(Some(status)),
+            libc::CLD_DUMPED => Ok(Some(status | 0x80)),
+            code => {
+                let msg = format!("unexpected waitid() code: {}", code);
+                Err(Error::new(ErrorKind::Other, msg))
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for PidFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+/// The output of a finished process, captured up to a byte limit per stream.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CappedOutput {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// Whether stdout produced more bytes than the limit, the rest of which were discarded.
+    pub stdout_truncated: bool,
+    /// Whether stderr produced more bytes than the limit, the rest of which were discarded.
+    pub stderr_truncated: bool,
+}
+
+#[derive(Debug, Default)]
+struct Capture {
+    bytes: Vec<u8>,
+    truncated: bool,
+}
+
+impl Capture {
+    fn push(&mut self, data: &[u8], limit: Option<usize>) {
+        let room = limit.map_or(data.len(), |limit| limit.saturating_sub(self.bytes.len()));
+        if data.len() > room {
+            self.truncated = true;
+        }
+
+        self.bytes.extend_from_slice(&data[..data.len().min(room)]);
+    }
+}
+
+/// Drains stdout and stderr at the same time, so a child filling up one pipe can't block forever
+/// while we are waiting on the other. This follows `read2()` in the standard library.
+fn read2(
+    mut stdout: Option<ChildStdout>,
+    mut stderr: Option<ChildStderr>,
+    limit: Option<usize>,
+) -> Result<(Capture, Capture), Error> {
+    let mut fds = [
+        stdout.as_ref().map(|p| p.0.as_raw_fd()),
+        stderr.as_ref().map(|p| p.0.as_raw_fd()),
+    ]
+    .iter()
+    .map(|fd| libc::pollfd {
+        // Negative descriptors are ignored by `poll()`.
+        fd: fd.unwrap_or(-1),
+        events: libc::POLLIN,
+        revents: 0,
+    })
+    .collect::<Vec<_>>();
+
+    for pollfd in fds.iter().filter(|p| p.fd >= 0) {
+        set_nonblocking(pollfd.fd)?;
+    }
+
+    let mut captures = [Capture::default(), Capture::default()];
+    let mut buf = [0u8; 8192];
+    while fds.iter().any(|p| p.fd >= 0) {
+        let nfds = fds.len() as libc::nfds_t;
+        util::catch_io_error_repeat(|| unsafe { libc::poll(fds.as_mut_ptr(), nfds, -1) })?;
+
+        for (i, pollfd) in fds.iter_mut().enumerate() {
+            if pollfd.fd < 0 || pollfd.revents == 0 {
+                continue;
+            }
+
+            let reader: &mut dyn Read = match i {
+                0 => stdout.as_mut().expect("stdout is being polled"),
+                _ => stderr.as_mut().expect("stderr is being polled"),
+            };
+
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => {
+                        pollfd.fd = -1;
+                        break;
+                    }
+                    Ok(n) => captures[i].push(&buf[..n], limit),
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
         }
     }
+
+    let [out, err] = captures;
+    Ok((out, err))
+}
+
+fn set_nonblocking(fd: RawFd) -> Result<(), Error> {
+    unsafe {
+        let flags = util::catch_io_error(libc::fcntl(fd, libc::F_GETFL))?;
+        util::catch_io_error(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+    }
+
+    Ok(())
+}
+
+/// Describes what to do with a standard I/O stream of a sandboxed process.
+///
+/// This mirrors `std::process::Stdio`, which can't be used here because `std::process::Command`
+/// offers no way of inspecting the stdio configuration it was given.
+#[derive(Clone, Debug)]
+pub struct Stdio(StdioKind);
+
+#[derive(Clone, Debug)]
+enum StdioKind {
+    Inherit,
+    Null,
+    Piped,
+    Merged,
+    Fd(Arc<File>),
+}
+
+impl Stdio {
+    /// The stream is inherited from the parent, unless configured on the `Command` itself.
+    pub fn inherit() -> Self {
+        Stdio(StdioKind::Inherit)
+    }
+
+    /// The stream is connected to `/dev/null` on the host.
+    pub fn null() -> Self {
+        Stdio(StdioKind::Null)
+    }
+
+    /// A new pipe is arranged to connect the parent and the sandboxed process.
+    pub fn piped() -> Self {
+        Stdio(StdioKind::Piped)
+    }
+
+    /// The stream is redirected into whatever stdout of the sandboxed process is connected to,
+    /// including a stdout configured on the `Command` itself.
+    ///
+    /// This is only valid for stderr, and spawning fails if used for stdin or stdout.
+    pub fn merged() -> Self {
+        Stdio(StdioKind::Merged)
+    }
+}
+
+impl Default for Stdio {
+    fn default() -> Self {
+        Stdio::inherit()
+    }
+}
+
+impl From<File> for Stdio {
+    fn from(file: File) -> Self {
+        Stdio(StdioKind::Fd(Arc::new(file)))
+    }
+}
+
+impl From<ChildStdin> for Stdio {
+    fn from(stdin: ChildStdin) -> Self {
+        unsafe { Stdio::from_raw_fd(stdin.into_raw_fd()) }
+    }
+}
+
+impl From<ChildStdout> for Stdio {
+    fn from(stdout: ChildStdout) -> Self {
+        unsafe { Stdio::from_raw_fd(stdout.into_raw_fd()) }
+    }
+}
+
+impl From<ChildStderr> for Stdio {
+    fn from(stderr: ChildStderr) -> Self {
+        unsafe { Stdio::from_raw_fd(stderr.into_raw_fd()) }
+    }
+}
+
+impl FromRawFd for Stdio {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Stdio::from(File::from_raw_fd(fd))
+    }
+}
+
+/// The ends of the standard I/O streams which are handed over to the sandboxed process. Streams
+/// set to `None` are left as configured on the `Command`.
+#[derive(Debug, Default)]
+pub(crate) struct ChildPipes {
+    stdin: Option<File>,
+    stdout: Option<File>,
+    stderr: Option<File>,
+    merge_stderr: bool,
+}
+
+impl ChildPipes {
+    /// Connects the streams to `command`. Called in the sandboxed process right before `exec()`.
+    pub fn apply(self, command: &mut Command) {
+        if let Some(stdin) = self.stdin {
+            command.stdin(stdin);
+        }
+        if let Some(stdout) = self.stdout {
+            command.stdout(stdout);
+        }
+        if let Some(stderr) = self.stderr {
+            command.stderr(stderr);
+        }
+
+        // The standard library sets up the streams of the `Command` before running this, so the
+        // effective stdout is merged into, even if it was only configured on the `Command`.
+        if self.merge_stderr {
+            unsafe {
+                command.pre_exec(|| {
+                    util::catch_io_error(libc::dup2(libc::STDOUT_FILENO, libc::STDERR_FILENO))?;
+                    Ok(())
+                });
+            }
+        }
+    }
+}
+
+/// The ends of the standard I/O streams which are kept by the parent.
+#[derive(Debug, Default)]
+pub(crate) struct ParentPipes {
+    pub stdin: Option<ChildStdin>,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+}
+
+/// Creates the pipes and files requested for each stream of the sandboxed process.
+pub(crate) fn create_pipes(
+    stdin: &Stdio,
+    stdout: &Stdio,
+    stderr: &Stdio,
+) -> Result<(ChildPipes, ParentPipes), Error> {
+    let mut child = ChildPipes::default();
+    let mut parent = ParentPipes::default();
+
+    match stdin.0 {
+        StdioKind::Inherit => {}
+        StdioKind::Null => child.stdin = Some(File::open("/dev/null")?),
+        StdioKind::Piped => {
+            let (reader, writer) = os_pipe::pipe()?;
+            child.stdin = Some(unsafe { File::from_raw_fd(reader.into_raw_fd()) });
+            parent.stdin = Some(ChildStdin(writer));
+        }
+        StdioKind::Merged => return Err(not_mergeable("stdin")),
+        StdioKind::Fd(ref file) => child.stdin = Some(file.try_clone()?),
+    }
+
+    match stdout.0 {
+        StdioKind::Inherit => {}
+        StdioKind::Null => child.stdout = Some(OpenOptions::new().write(true).open("/dev/null")?),
+        StdioKind::Piped => {
+            let (reader, writer) = os_pipe::pipe()?;
+            child.stdout = Some(unsafe { File::from_raw_fd(writer.into_raw_fd()) });
+            parent.stdout = Some(ChildStdout(reader));
+        }
+        StdioKind::Merged => return Err(not_mergeable("stdout")),
+        StdioKind::Fd(ref file) => child.stdout = Some(file.try_clone()?),
+    }
+
+    match stderr.0 {
+        StdioKind::Inherit => {}
+        StdioKind::Null => child.stderr = Some(OpenOptions::new().write(true).open("/dev/null")?),
+        StdioKind::Piped => {
+            let (reader, writer) = os_pipe::pipe()?;
+            child.stderr = Some(unsafe { File::from_raw_fd(writer.into_raw_fd()) });
+            parent.stderr = Some(ChildStderr(reader));
+        }
+        StdioKind::Merged => child.merge_stderr = true,
+        StdioKind::Fd(ref file) => child.stderr = Some(file.try_clone()?),
+    }
+
+    Ok((child, parent))
+}
+
+fn not_mergeable(stream: &str) -> Error {
+    let msg = format!(
+        "invalid argument: only stderr can be merged, not {}",
+        stream
+    );
+    Error::new(ErrorKind::InvalidInput, msg)
 }
 
 #[derive(Debug)]
@@ -0,0 +1,215 @@
+//! Asynchronous counterpart of `bastille::process` for the [tokio] runtime.
+//!
+//! Rather than blocking a thread in `waitpid()` per sandbox, the exit of a sandboxed process is
+//! awaited through a pidfd registered with the tokio reactor, which becomes readable once the
+//! process has exited. This requires Linux 5.3 or newer.
+//!
+//! [tokio]: https://tokio.rs/
+
+use std::fs::File;
+use std::io::Error;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::pin::Pin;
+use std::process::{ExitStatus, Output};
+use std::task::{Context, Poll};
+
+use libc::c_int;
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::net::unix::pipe::{Receiver, Sender};
+use tokio::task::{self, JoinHandle};
+
+use crate::cgroup::Cgroup;
+use crate::util;
+
+#[derive(Debug)]
+pub struct Child {
+    pub stdin: Option<ChildStdin>,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+    inner: super::Child,
+    pidfd: AsyncFd<File>,
+    cgroup: Option<Cgroup>,
+}
+
+impl Child {
+    /// Converts a blocking `Child` into an asynchronous one.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if it is not called from within a tokio runtime with I/O enabled.
+    pub fn from_std(mut child: super::Child) -> Result<Self, Error> {
+        let pidfd = match child.pidfd {
+            Some(ref pidfd) => pidfd.0.try_clone()?,
+            None => unsafe {
+                let fd = libc::syscall(libc::SYS_pidfd_open, child.pid, 0) as c_int;
+                File::from_raw_fd(util::catch_io_error(fd)?)
+            },
+        };
+
+        let stdin = child.stdin.take().map(|p| Sender::from_file(into_file(p)));
+        let stdout = child
+            .stdout
+            .take()
+            .map(|p| Receiver::from_file(into_file(p)));
+        let stderr = child
+            .stderr
+            .take()
+            .map(|p| Receiver::from_file(into_file(p)));
+
+        Ok(Child {
+            stdin: stdin.transpose()?.map(ChildStdin),
+            stdout: stdout.transpose()?.map(ChildStdout),
+            stderr: stderr.transpose()?.map(ChildStderr),
+            cgroup: child.cgroup.take(),
+            inner: child,
+            pidfd: AsyncFd::new(pidfd)?,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.inner.id()
+    }
+
+    pub async fn wait(&mut self) -> Result<ExitStatus, Error> {
+        drop(self.stdin.take());
+
+        let status = match self.inner.try_wait()? {
+            Some(status) => status,
+            None => loop {
+                let mut guard = self.pidfd.readable().await?;
+                if let Some(status) = self.inner.try_wait()? {
+                    break status;
+                }
+
+                guard.clear_ready();
+            },
+        };
+
+        if let Some(removal) = self.remove_cgroup() {
+            removal.await?;
+        }
+
+        Ok(status)
+    }
+
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>, Error> {
+        let status = self.inner.try_wait()?;
+        if status.is_some() {
+            // Nobody awaits the removal, but the cgroup is gone soon after.
+            self.remove_cgroup();
+        }
+
+        Ok(status)
+    }
+
+    pub async fn wait_with_output(mut self) -> Result<Output, Error> {
+        drop(self.stdin.take());
+
+        let (stdout, stderr) = (self.stdout.take(), self.stderr.take());
+        let (stdout, stderr, status) =
+            tokio::try_join!(read_to_end(stdout), read_to_end(stderr), self.wait())?;
+
+        Ok(Output {
+            status,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Sends `SIGKILL` to the process without waiting for it to exit.
+    pub fn start_kill(&mut self) -> Result<(), Error> {
+        self.inner.kill()
+    }
+
+    /// Sends `SIGKILL` to the process and waits for it to exit.
+    pub async fn kill(&mut self) -> Result<(), Error> {
+        self.start_kill()?;
+        self.wait().await.map(|_| ())
+    }
+
+    /// Removes the cgroup of the reaped process on the blocking thread pool, since it may take a
+    /// moment for the kernel to notice that the processes left behind in it were killed.
+    fn remove_cgroup(&mut self) -> Option<JoinHandle<()>> {
+        let cgroup = self.cgroup.take()?;
+        Some(task::spawn_blocking(move || cgroup.remove()))
+    }
+}
+
+fn into_file<T: IntoRawFd>(pipe: T) -> File {
+    unsafe { File::from_raw_fd(pipe.into_raw_fd()) }
+}
+
+async fn read_to_end<R: AsyncRead + Unpin>(reader: Option<R>) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    if let Some(mut reader) = reader {
+        reader.read_to_end(&mut buf).await?;
+    }
+
+    Ok(buf)
+}
+
+#[derive(Debug)]
+pub struct ChildStdin(Sender);
+
+impl AsyncWrite for ChildStdin {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+impl AsRawFd for ChildStdin {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+#[derive(Debug)]
+pub struct ChildStdout(Receiver);
+
+impl AsyncRead for ChildStdout {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsRawFd for ChildStdout {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+#[derive(Debug)]
+pub struct ChildStderr(Receiver);
+
+impl AsyncRead for ChildStderr {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsRawFd for ChildStderr {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
@@ -0,0 +1,129 @@
+//! POSIX resource limits for sandboxed processes.
+//!
+//! Unlike cgroups, these need no delegation, but they only apply per process (or per user, in the
+//! case of `Nproc`) and are inherited by its children. They are set right before the command is
+//! executed.
+
+use libc::c_int;
+
+use std::io;
+
+use crate::util;
+
+/// A value standing for no limit at all.
+pub const INFINITY: u64 = libc::RLIM_INFINITY as _;
+
+/// A resource which can be limited with `setrlimit()`, named after its `RLIMIT_*` constant.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Resource {
+    /// Maximum size of the virtual memory of the process, in bytes.
+    As,
+    /// Maximum size of core dumps, in bytes.
+    Core,
+    /// CPU time, in seconds. The process receives `SIGXCPU` once the soft limit is reached, and
+    /// `SIGKILL` at the hard limit.
+    Cpu,
+    /// Maximum size of the data segment of the process, in bytes.
+    Data,
+    /// Maximum size of files written by the process, in bytes. Writing past it raises `SIGXFSZ`.
+    Fsize,
+    /// Maximum number of file locks held by the process (Linux only).
+    #[cfg(target_os = "linux")]
+    Locks,
+    /// Maximum number of bytes of memory which may be locked into RAM.
+    Memlock,
+    /// Maximum number of bytes in POSIX message queues of the real user (Linux only).
+    #[cfg(target_os = "linux")]
+    Msgqueue,
+    /// Ceiling of the nice value of the process, as `20 - limit` (Linux only).
+    #[cfg(target_os = "linux")]
+    Nice,
+    /// One more than the highest file descriptor number the process may open.
+    Nofile,
+    /// Maximum number of processes and threads of the real user.
+    Nproc,
+    /// Maximum resident set size, in bytes. Only enforced by some platforms.
+    Rss,
+    /// Ceiling of the real-time priority of the process (Linux only).
+    #[cfg(target_os = "linux")]
+    Rtprio,
+    /// CPU time under a real-time scheduling policy without blocking, in microseconds (Linux
+    /// only).
+    #[cfg(target_os = "linux")]
+    Rttime,
+    /// Maximum number of signals queued for the real user (Linux only).
+    #[cfg(target_os = "linux")]
+    Sigpending,
+    /// Maximum size of the stack of the process, in bytes.
+    Stack,
+}
+
+impl Resource {
+    fn to_raw(self) -> c_int {
+        let raw = match self {
+            Resource::As => libc::RLIMIT_AS,
+            Resource::Core => libc::RLIMIT_CORE,
+            Resource::Cpu => libc::RLIMIT_CPU,
+            Resource::Data => libc::RLIMIT_DATA,
+            Resource::Fsize => libc::RLIMIT_FSIZE,
+            #[cfg(target_os = "linux")]
+            Resource::Locks => libc::RLIMIT_LOCKS,
+            Resource::Memlock => libc::RLIMIT_MEMLOCK,
+            #[cfg(target_os = "linux")]
+            Resource::Msgqueue => libc::RLIMIT_MSGQUEUE,
+            #[cfg(target_os = "linux")]
+            Resource::Nice => libc::RLIMIT_NICE,
+            Resource::Nofile => libc::RLIMIT_NOFILE,
+            Resource::Nproc => libc::RLIMIT_NPROC,
+            Resource::Rss => libc::RLIMIT_RSS,
+            #[cfg(target_os = "linux")]
+            Resource::Rtprio => libc::RLIMIT_RTPRIO,
+            #[cfg(target_os = "linux")]
+            Resource::Rttime => libc::RLIMIT_RTTIME,
+            #[cfg(target_os = "linux")]
+            Resource::Sigpending => libc::RLIMIT_SIGPENDING,
+            Resource::Stack => libc::RLIMIT_STACK,
+        };
+
+        raw as c_int
+    }
+
+    /// Returns the resource whose limit is enforced by sending `signal`, if any.
+    pub(crate) fn from_signal(signal: c_int) -> Option<Resource> {
+        match signal {
+            libc::SIGXCPU => Some(Resource::Cpu),
+            libc::SIGXFSZ => Some(Resource::Fsize),
+            _ => None,
+        }
+    }
+}
+
+/// A soft and hard limit on a resource.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Rlimit {
+    pub resource: Resource,
+    pub soft: u64,
+    pub hard: u64,
+}
+
+/// Applies `limits` to the calling process, in order.
+pub(crate) fn apply(limits: &[Rlimit]) -> Result<(), io::Error> {
+    for limit in limits {
+        let rlimit = libc::rlimit {
+            rlim_cur: limit.soft as libc::rlim_t,
+            rlim_max: limit.hard as libc::rlim_t,
+        };
+
+        let res = unsafe { libc::setrlimit(limit.resource.to_raw() as _, &rlimit) };
+        util::catch_io_error(res).map_err(|e| {
+            // The errno alone doesn't say which of the limits was rejected.
+            let message = format!(
+                "{:?} (soft {}, hard {}): {}",
+                limit.resource, limit.soft, limit.hard, e
+            );
+            io::Error::new(e.kind(), message)
+        })?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,896 @@
+//! Seccomp-BPF system call filtering for sandboxed processes (Linux only).
+//!
+//! A `Policy` lists rules which are checked in order, with the first rule matching a system call
+//! deciding its fate and the default action applying otherwise. Policies are compiled to classic
+//! BPF for the running architecture when the sandbox is spawned, and installed in the sandboxed
+//! process right before the command is executed.
+//!
+//! Note that the filter is already in effect while `exec()` runs, so a policy which denies by
+//! default must allow `execve` and whatever the dynamic loader of the command needs.
+
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::io::{self, ErrorKind};
+use std::mem;
+
+use libc::{c_long, sock_filter, sock_fprog};
+
+use crate::{util, Error};
+
+/// Maximum number of instructions in a filter program, as defined by the kernel.
+const BPF_MAXINSNS: usize = 4096;
+
+/// `BPF_LD | BPF_W | BPF_ABS`
+const BPF_LD_W_ABS: u16 = 0x20;
+/// `BPF_ALU | BPF_AND | BPF_K`
+const BPF_ALU_AND_K: u16 = 0x54;
+/// `BPF_JMP | BPF_JEQ | BPF_K`
+const BPF_JMP_JEQ_K: u16 = 0x15;
+/// `BPF_JMP | BPF_JGT | BPF_K`
+const BPF_JMP_JGT_K: u16 = 0x25;
+/// `BPF_JMP | BPF_JGE | BPF_K`
+const BPF_JMP_JGE_K: u16 = 0x35;
+/// `BPF_RET | BPF_K`
+const BPF_RET_K: u16 = 0x06;
+
+/// Offsets into `struct seccomp_data`.
+const DATA_NR: u32 = 0;
+const DATA_ARCH: u32 = 4;
+const DATA_ARGS: u32 = 16;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xC000_003E);
+#[cfg(target_arch = "x86")]
+const AUDIT_ARCH: Option<u32> = Some(0x4000_0003);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xC000_00B7);
+#[cfg(target_arch = "arm")]
+const AUDIT_ARCH: Option<u32> = Some(0x4000_0028);
+#[cfg(target_arch = "riscv64")]
+const AUDIT_ARCH: Option<u32> = Some(0xC000_00F3);
+#[cfg(all(target_arch = "powerpc64", target_endian = "little"))]
+const AUDIT_ARCH: Option<u32> = Some(0xC000_0015);
+#[cfg(target_arch = "s390x")]
+const AUDIT_ARCH: Option<u32> = Some(0x8000_0016);
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "x86",
+    target_arch = "aarch64",
+    target_arch = "arm",
+    target_arch = "riscv64",
+    all(target_arch = "powerpc64", target_endian = "little"),
+    target_arch = "s390x",
+)))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// Every `CLONE_NEW*` flag which can be passed to `clone()`. `CLONE_NEWTIME` is left out, since it
+/// overlaps with the exit signal there and is only accepted by `unshare()` and `clone3()`.
+const NAMESPACE_FLAGS: u64 = (libc::CLONE_NEWNS
+    | libc::CLONE_NEWCGROUP
+    | libc::CLONE_NEWUTS
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUSER
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWNET) as u64;
+
+const PER_LINUX: u64 = 0x0000;
+const PER_LINUX32: u64 = 0x0008;
+/// Passing `0xffffffff` to `personality()` only queries the current persona.
+const PER_QUERY: u64 = 0xFFFF_FFFF;
+
+/// `kexec_file_load` exists on every architecture with an `AUDIT_ARCH` except 32-bit x86, but
+/// `libc` only defines it for some of them, and not for every C library.
+#[cfg(any(target_arch = "x86_64", target_arch = "powerpc64"))]
+const SYS_KEXEC_FILE_LOAD: Option<c_long> = Some(libc::SYS_kexec_file_load);
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+const SYS_KEXEC_FILE_LOAD: Option<c_long> = Some(294);
+#[cfg(target_arch = "arm")]
+const SYS_KEXEC_FILE_LOAD: Option<c_long> = Some(401);
+#[cfg(target_arch = "s390x")]
+const SYS_KEXEC_FILE_LOAD: Option<c_long> = Some(381);
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "powerpc64",
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "arm",
+    target_arch = "s390x",
+)))]
+const SYS_KEXEC_FILE_LOAD: Option<c_long> = None;
+
+/// `socketcall()` multiplexes the socket system calls on 32-bit x86, powerpc64 and s390x, and
+/// passes their arguments through a pointer which a filter can't follow.
+#[cfg(any(target_arch = "x86", target_arch = "powerpc64", target_arch = "s390x"))]
+const SYS_SOCKETCALL: Option<c_long> = Some(libc::SYS_socketcall);
+#[cfg(not(any(target_arch = "x86", target_arch = "powerpc64", target_arch = "s390x")))]
+const SYS_SOCKETCALL: Option<c_long> = None;
+/// The `socket()` and `socketpair()` calls of `socketcall()`, as defined by `linux/net.h`.
+const SOCKETCALL_SOCKET: u64 = 1;
+const SOCKETCALL_SOCKETPAIR: u64 = 8;
+
+/// System calls of the x32 ABI share the x86_64 audit architecture, but have this bit set.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: Option<u32> = Some(0x4000_0000);
+#[cfg(not(target_arch = "x86_64"))]
+const X32_SYSCALL_BIT: Option<u32> = None;
+
+/// What happens to a system call.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// The system call is executed.
+    Allow,
+    /// The system call fails with the given `errno`, without being executed.
+    Errno(i32),
+    /// The whole process is killed, as if by `SIGSYS`.
+    Kill,
+    /// The thread receives `SIGSYS`, which it may handle.
+    Trap,
+}
+
+impl Action {
+    fn to_ret(self) -> u32 {
+        match self {
+            Action::Allow => libc::SECCOMP_RET_ALLOW,
+            Action::Errno(errno) => {
+                libc::SECCOMP_RET_ERRNO | (errno as u32 & libc::SECCOMP_RET_DATA)
+            }
+            Action::Kill => libc::SECCOMP_RET_KILL_PROCESS,
+            Action::Trap => libc::SECCOMP_RET_TRAP,
+        }
+    }
+}
+
+/// A comparison against a system call argument, which is treated as an unsigned 64-bit value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Comparison {
+    Equal(u64),
+    NotEqual(u64),
+    Less(u64),
+    LessOrEqual(u64),
+    Greater(u64),
+    GreaterOrEqual(u64),
+    /// The argument is equal to `value` after masking it with `mask`.
+    MaskedEqual {
+        mask: u64,
+        value: u64,
+    },
+}
+
+/// A rule which applies `action` to a system call, if all of its argument comparisons hold.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rule {
+    syscall: c_long,
+    args: Vec<(u8, Comparison)>,
+    action: Action,
+}
+
+impl Rule {
+    /// Creates a rule matching every invocation of `syscall`, which is one of the `libc::SYS_*`
+    /// constants.
+    pub fn new(syscall: c_long, action: Action) -> Self {
+        Rule {
+            syscall,
+            args: Vec::new(),
+            action,
+        }
+    }
+
+    /// Narrows the rule down to invocations where argument `index` (0 to 5) satisfies `cmp`.
+    pub fn arg(mut self, index: u8, cmp: Comparison) -> Self {
+        self.args.push((index, cmp));
+        self
+    }
+}
+
+/// A seccomp policy made up of rules and a default action.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Policy {
+    default: Action,
+    rules: Vec<Rule>,
+}
+
+impl Policy {
+    pub fn new(default: Action) -> Self {
+        Policy {
+            default,
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn rule(&mut self, rule: Rule) -> &mut Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn allow(&mut self, syscall: c_long) -> &mut Self {
+        self.rule(Rule::new(syscall, Action::Allow))
+    }
+
+    /// Makes `syscall` fail with `EPERM`.
+    pub fn deny(&mut self, syscall: c_long) -> &mut Self {
+        self.rule(Rule::new(syscall, Action::Errno(libc::EPERM)))
+    }
+
+    pub fn errno(&mut self, syscall: c_long, errno: i32) -> &mut Self {
+        self.rule(Rule::new(syscall, Action::Errno(errno)))
+    }
+
+    pub fn kill(&mut self, syscall: c_long) -> &mut Self {
+        self.rule(Rule::new(syscall, Action::Kill))
+    }
+
+    pub fn trap(&mut self, syscall: c_long) -> &mut Self {
+        self.rule(Rule::new(syscall, Action::Trap))
+    }
+
+    /// Compiles the policy to a BPF program for the running architecture.
+    pub(crate) fn compile(&self) -> Result<Filter, Error> {
+        let arch = AUDIT_ARCH.ok_or_else(|| invalid("unsupported architecture"))?;
+
+        let mut program = vec![
+            stmt(BPF_LD_W_ABS, DATA_ARCH),
+            jump(BPF_JMP_JEQ_K, arch, 1, 0),
+            stmt(BPF_RET_K, libc::SECCOMP_RET_KILL_PROCESS),
+        ];
+
+        if let Some(bit) = X32_SYSCALL_BIT {
+            program.push(stmt(BPF_LD_W_ABS, DATA_NR));
+            program.push(jump(BPF_JMP_JGE_K, bit, 0, 1));
+            program.push(stmt(BPF_RET_K, libc::SECCOMP_RET_KILL_PROCESS));
+        }
+
+        for rule in &self.rules {
+            program.extend(compile_rule(rule)?);
+        }
+
+        program.push(stmt(BPF_RET_K, self.default.to_ret()));
+
+        if program.len() > BPF_MAXINSNS {
+            return Err(invalid("too many rules"));
+        }
+
+        Ok(Filter(program))
+    }
+}
+
+/// A preset denying the system calls which Bubblewrap and Flatpak block by default.
+///
+/// Every category is enabled by default and can be switched off individually. The resulting
+/// filter allows everything else, and is installed alongside any custom `Policy`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Hardening {
+    ptrace: bool,
+    keyring: bool,
+    perf_events: bool,
+    userfaultfd: bool,
+    bpf: bool,
+    namespaces: bool,
+    mount: bool,
+    kexec: bool,
+    terminal_injection: bool,
+    personality: bool,
+    socket_families: bool,
+}
+
+impl Hardening {
+    pub fn new() -> Self {
+        Hardening {
+            ptrace: true,
+            keyring: true,
+            perf_events: true,
+            userfaultfd: true,
+            bpf: true,
+            namespaces: true,
+            mount: true,
+            kexec: true,
+            terminal_injection: true,
+            personality: true,
+            socket_families: true,
+        }
+    }
+
+    /// Denies `ptrace`, which could be used to tamper with other processes in the sandbox.
+    pub fn ptrace(mut self, enabled: bool) -> Self {
+        self.ptrace = enabled;
+        self
+    }
+
+    /// Denies `add_key`, `keyctl` and `request_key`, since the kernel keyring isn't namespaced.
+    pub fn keyring(mut self, enabled: bool) -> Self {
+        self.keyring = enabled;
+        self
+    }
+
+    /// Denies `perf_event_open`, which exposes a large kernel attack surface.
+    pub fn perf_events(mut self, enabled: bool) -> Self {
+        self.perf_events = enabled;
+        self
+    }
+
+    /// Denies `userfaultfd`, a common building block for kernel exploits.
+    pub fn userfaultfd(mut self, enabled: bool) -> Self {
+        self.userfaultfd = enabled;
+        self
+    }
+
+    /// Denies `bpf`.
+    pub fn bpf(mut self, enabled: bool) -> Self {
+        self.bpf = enabled;
+        self
+    }
+
+    /// Denies `unshare`, `setns` and `clone` with namespace flags, so the sandboxed process can't
+    /// create nested namespaces. `clone3` fails with `ENOSYS`, which makes the C library fall back
+    /// to `clone`, as its flags can't be inspected.
+    pub fn namespaces(mut self, enabled: bool) -> Self {
+        self.namespaces = enabled;
+        self
+    }
+
+    /// Denies `mount`, `umount2`, `pivot_root`, `chroot` and the new mount API.
+    pub fn mount(mut self, enabled: bool) -> Self {
+        self.mount = enabled;
+        self
+    }
+
+    /// Denies `kexec_load` and `kexec_file_load`, where the architecture has the latter.
+    pub fn kexec(mut self, enabled: bool) -> Self {
+        self.kexec = enabled;
+        self
+    }
+
+    /// Denies the `TIOCSTI` and `TIOCLINUX` ioctls, which can inject input into the terminal of
+    /// the parent (CVE-2017-5226).
+    pub fn terminal_injection(mut self, enabled: bool) -> Self {
+        self.terminal_injection = enabled;
+        self
+    }
+
+    /// Denies `personality` changes other than `PER_LINUX` and `PER_LINUX32`, which could disable
+    /// ASLR among other things.
+    pub fn personality(mut self, enabled: bool) -> Self {
+        self.personality = enabled;
+        self
+    }
+
+    /// Makes `socket` fail with `EAFNOSUPPORT` for families other than `AF_UNSPEC`, `AF_UNIX`,
+    /// `AF_INET`, `AF_INET6` and `AF_NETLINK`, since obsolete families are poorly maintained.
+    pub fn socket_families(mut self, enabled: bool) -> Self {
+        self.socket_families = enabled;
+        self
+    }
+
+    pub(crate) fn policy(&self) -> Policy {
+        let mut policy = Policy::new(Action::Allow);
+
+        if self.ptrace {
+            policy.deny(libc::SYS_ptrace);
+        }
+
+        if self.keyring {
+            policy.deny(libc::SYS_add_key);
+            policy.deny(libc::SYS_keyctl);
+            policy.deny(libc::SYS_request_key);
+        }
+
+        if self.perf_events {
+            policy.deny(libc::SYS_perf_event_open);
+        }
+
+        if self.userfaultfd {
+            policy.deny(libc::SYS_userfaultfd);
+        }
+
+        if self.bpf {
+            policy.deny(libc::SYS_bpf);
+        }
+
+        if self.namespaces {
+            // The flags are the second argument of `clone()` on s390x.
+            let flags = if cfg!(target_arch = "s390x") { 1 } else { 0 };
+            let no_namespaces = Comparison::MaskedEqual {
+                mask: NAMESPACE_FLAGS,
+                value: 0,
+            };
+
+            policy.rule(Rule::new(libc::SYS_clone, Action::Allow).arg(flags, no_namespaces));
+            policy.deny(libc::SYS_clone);
+            policy.errno(libc::SYS_clone3, libc::ENOSYS);
+            policy.deny(libc::SYS_unshare);
+            policy.deny(libc::SYS_setns);
+        }
+
+        if self.mount {
+            policy.deny(libc::SYS_mount);
+            policy.deny(libc::SYS_umount2);
+            policy.deny(libc::SYS_pivot_root);
+            policy.deny(libc::SYS_chroot);
+            policy.deny(libc::SYS_open_tree);
+            policy.deny(libc::SYS_move_mount);
+            policy.deny(libc::SYS_fsopen);
+            policy.deny(libc::SYS_fsconfig);
+            policy.deny(libc::SYS_fsmount);
+            policy.deny(libc::SYS_fspick);
+        }
+
+        if self.kexec {
+            policy.deny(libc::SYS_kexec_load);
+            if let Some(syscall) = SYS_KEXEC_FILE_LOAD {
+                policy.deny(syscall);
+            }
+        }
+
+        if self.terminal_injection {
+            deny_terminal_injection(&mut policy);
+        }
+
+        if self.personality {
+            let rule = Rule::new(libc::SYS_personality, Action::Errno(libc::EPERM))
+                .arg(0, Comparison::NotEqual(PER_LINUX))
+                .arg(0, Comparison::NotEqual(PER_LINUX32))
+                .arg(0, Comparison::NotEqual(PER_QUERY));
+            policy.rule(rule);
+        }
+
+        if self.socket_families {
+            let unsupported = Action::Errno(libc::EAFNOSUPPORT);
+            let ranges = [
+                (libc::AF_INET + 1, libc::AF_INET6 - 1),
+                (libc::AF_INET6 + 1, libc::AF_NETLINK - 1),
+            ];
+
+            for &(first, last) in &ranges {
+                let rule = Rule::new(libc::SYS_socket, unsupported)
+                    .arg(0, Comparison::GreaterOrEqual(first as u64))
+                    .arg(0, Comparison::LessOrEqual(last as u64));
+                policy.rule(rule);
+            }
+
+            let rule = Rule::new(libc::SYS_socket, unsupported)
+                .arg(0, Comparison::Greater(libc::AF_NETLINK as u64));
+            policy.rule(rule);
+        }
+
+        policy
+    }
+}
+
+impl Default for Hardening {
+    fn default() -> Self {
+        Hardening::new()
+    }
+}
+
+/// A policy which only denies the `TIOCSTI` and `TIOCLINUX` ioctls.
+pub(crate) fn terminal_injection_policy() -> Policy {
+    let mut policy = Policy::new(Action::Allow);
+    deny_terminal_injection(&mut policy);
+    policy
+}
+
+/// A policy which denies creating `AF_UNIX` sockets, keeping the sandboxed process from connecting
+/// to any host socket it can see as well as from creating socket pairs.
+///
+/// On 32-bit x86, powerpc64 and s390x, sockets may also be created through `socketcall()`, whose
+/// family argument can't be inspected, so that would bypass the filter. Creating any socket
+/// through it is denied there, which leaves programs only the direct `socket()` and `socketpair()`
+/// of Linux 4.3+.
+pub(crate) fn local_sockets_policy() -> Policy {
+    let mut policy = Policy::new(Action::Allow);
+    for &syscall in &[libc::SYS_socket, libc::SYS_socketpair] {
+        let rule = Rule::new(syscall, Action::Errno(libc::EPERM));
+        policy.rule(rule.arg(0, Comparison::Equal(libc::AF_UNIX as u64)));
+    }
+
+    if let Some(syscall) = SYS_SOCKETCALL {
+        for &call in &[SOCKETCALL_SOCKET, SOCKETCALL_SOCKETPAIR] {
+            let rule = Rule::new(syscall, Action::Errno(libc::EPERM));
+            policy.rule(rule.arg(0, Comparison::Equal(call)));
+        }
+    }
+
+    policy
+}
+
+fn deny_terminal_injection(policy: &mut Policy) {
+    // Only the lower 32 bits are compared, since the request is an `int` in the kernel.
+    for &request in &[libc::TIOCSTI, libc::TIOCLINUX] {
+        let request = Comparison::MaskedEqual {
+            mask: 0xFFFF_FFFF,
+            // The request is a `c_ulong` on glibc, but a `c_int` on musl.
+            value: request as _,
+        };
+
+        let rule = Rule::new(libc::SYS_ioctl, Action::Errno(libc::EPERM));
+        policy.rule(rule.arg(1, request));
+    }
+}
+
+/// A compiled policy, ready to be installed.
+#[derive(Clone)]
+pub(crate) struct Filter(Vec<sock_filter>);
+
+// `sock_filter` only implements `Debug` with the `extra_traits` feature of `libc`.
+impl Debug for Filter {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("Filter")
+            .field("len", &self.0.len())
+            .finish()
+    }
+}
+
+impl Filter {
+    /// Installs the filter for the calling thread. This requires `PR_SET_NO_NEW_PRIVS` to be set.
+    pub unsafe fn install(&self) -> Result<(), Error> {
+        let prog = sock_fprog {
+            len: self.0.len() as u16,
+            filter: self.0.as_ptr() as *mut sock_filter,
+        };
+
+        let mode = libc::SECCOMP_MODE_FILTER as libc::c_ulong;
+        util::catch_io_error(libc::prctl(libc::PR_SET_SECCOMP, mode, &prog as *const _))
+            .map_err(Error::Seccomp)?;
+
+        Ok(())
+    }
+}
+
+/// A jump target within a rule.
+#[derive(Clone, Copy)]
+enum Target {
+    /// The following instruction.
+    Next,
+    /// The instruction at the given index within the rule.
+    At(usize),
+    /// The first instruction of the next rule.
+    NextRule,
+}
+
+/// An instruction with symbolic jump targets, resolved to relative offsets once the length of the
+/// rule is known.
+struct Insn {
+    code: u16,
+    k: u32,
+    jt: Target,
+    jf: Target,
+}
+
+impl Insn {
+    fn stmt(code: u16, k: u32) -> Self {
+        Insn::jump(code, k, Target::Next, Target::Next)
+    }
+
+    fn jump(code: u16, k: u32, jt: Target, jf: Target) -> Self {
+        Insn { code, k, jt, jf }
+    }
+}
+
+fn compile_rule(rule: &Rule) -> Result<Vec<sock_filter>, Error> {
+    let nr = rule.syscall as u32;
+    let mut insns = vec![
+        Insn::stmt(BPF_LD_W_ABS, DATA_NR),
+        Insn::jump(BPF_JMP_JEQ_K, nr, Target::Next, Target::NextRule),
+    ];
+
+    for &(index, cmp) in &rule.args {
+        if index > 5 {
+            return Err(invalid("system call argument index out of range"));
+        }
+
+        compile_comparison(&mut insns, index, cmp);
+    }
+
+    insns.push(Insn::stmt(BPF_RET_K, rule.action.to_ret()));
+
+    let len = insns.len();
+    let resolve = |at: usize, target: Target| -> Result<u8, Error> {
+        let offset = match target {
+            Target::Next => 0,
+            Target::At(target) => target - at - 1,
+            Target::NextRule => len - at - 1,
+        };
+
+        if offset > u8::MAX as usize {
+            return Err(invalid("too many argument comparisons in one rule"));
+        }
+
+        Ok(offset as u8)
+    };
+
+    let mut program = Vec::with_capacity(len);
+    for (at, insn) in insns.iter().enumerate() {
+        program.push(sock_filter {
+            code: insn.code,
+            jt: resolve(at, insn.jt)?,
+            jf: resolve(at, insn.jf)?,
+            k: insn.k,
+        });
+    }
+
+    Ok(program)
+}
+
+/// Appends the instructions checking `cmp`, which fall through past them if the comparison holds
+/// and jump to the next rule otherwise.
+///
+/// Classic BPF only operates on 32-bit words, so each 64-bit argument is compared in two halves,
+/// starting with the upper one.
+fn compile_comparison(insns: &mut Vec<Insn>, index: u8, cmp: Comparison) {
+    let (hi_offset, lo_offset) = arg_offsets(index);
+    let split = |value: u64| ((value >> 32) as u32, value as u32);
+    let (next, fail) = (Target::Next, Target::NextRule);
+    let start = insns.len();
+
+    insns.push(Insn::stmt(BPF_LD_W_ABS, hi_offset));
+    match cmp {
+        Comparison::Equal(value) => {
+            let (hi, lo) = split(value);
+            insns.push(Insn::jump(BPF_JMP_JEQ_K, hi, next, fail));
+            insns.push(Insn::stmt(BPF_LD_W_ABS, lo_offset));
+            insns.push(Insn::jump(BPF_JMP_JEQ_K, lo, next, fail));
+        }
+        Comparison::NotEqual(value) => {
+            let (hi, lo) = split(value);
+            let pass = Target::At(start + 4);
+            insns.push(Insn::jump(BPF_JMP_JEQ_K, hi, next, pass));
+            insns.push(Insn::stmt(BPF_LD_W_ABS, lo_offset));
+            insns.push(Insn::jump(BPF_JMP_JEQ_K, lo, fail, next));
+        }
+        Comparison::Greater(value) | Comparison::GreaterOrEqual(value) => {
+            let (hi, lo) = split(value);
+            let pass = Target::At(start + 5);
+            let code = match cmp {
+                Comparison::Greater(_) => BPF_JMP_JGT_K,
+                _ => BPF_JMP_JGE_K,
+            };
+            insns.push(Insn::jump(BPF_JMP_JGT_K, hi, pass, next));
+            insns.push(Insn::jump(BPF_JMP_JEQ_K, hi, next, fail));
+            insns.push(Insn::stmt(BPF_LD_W_ABS, lo_offset));
+            insns.push(Insn::jump(code, lo, next, fail));
+        }
+        Comparison::Less(value) | Comparison::LessOrEqual(value) => {
+            let (hi, lo) = split(value);
+            let pass = Target::At(start + 5);
+            let code = match cmp {
+                Comparison::Less(_) => BPF_JMP_JGE_K,
+                _ => BPF_JMP_JGT_K,
+            };
+            insns.push(Insn::jump(BPF_JMP_JGT_K, hi, fail, next));
+            insns.push(Insn::jump(BPF_JMP_JEQ_K, hi, next, pass));
+            insns.push(Insn::stmt(BPF_LD_W_ABS, lo_offset));
+            insns.push(Insn::jump(code, lo, fail, next));
+        }
+        Comparison::MaskedEqual { mask, value } => {
+            let ((hi_mask, lo_mask), (hi, lo)) = (split(mask), split(value));
+            insns.push(Insn::stmt(BPF_ALU_AND_K, hi_mask));
+            insns.push(Insn::jump(BPF_JMP_JEQ_K, hi & hi_mask, next, fail));
+            insns.push(Insn::stmt(BPF_LD_W_ABS, lo_offset));
+            insns.push(Insn::stmt(BPF_ALU_AND_K, lo_mask));
+            insns.push(Insn::jump(BPF_JMP_JEQ_K, lo & lo_mask, next, fail));
+        }
+    }
+}
+
+fn arg_offsets(index: u8) -> (u32, u32) {
+    let base = DATA_ARGS + u32::from(index) * mem::size_of::<u64>() as u32;
+    if cfg!(target_endian = "little") {
+        (base + 4, base)
+    } else {
+        (base, base + 4)
+    }
+}
+
+fn stmt(code: u16, k: u32) -> sock_filter {
+    jump(code, k, 0, 0)
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter { code, jt, jf, k }
+}
+
+fn invalid(msg: &str) -> Error {
+    let msg = format!("invalid seccomp policy: {}", msg);
+    Error::Seccomp(io::Error::new(ErrorKind::InvalidInput, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use libc::c_int;
+
+    /// Runs `f` in a forked child with `policy` installed, returning the errno it failed with, or
+    /// zero if it succeeded. The filter is compiled up front, since the child may not allocate.
+    fn errno_under(policy: &Policy, f: impl FnOnce() -> c_long) -> i32 {
+        let filter = policy.compile().expect("failed to compile policy");
+        unsafe {
+            let pid = libc::fork();
+            assert_ne!(pid, -1, "failed to fork");
+            if pid == 0 {
+                libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0);
+                if filter.install().is_err() {
+                    libc::_exit(255);
+                }
+                let errno = match f() {
+                    -1 => io::Error::last_os_error().raw_os_error().unwrap_or(254),
+                    _ => 0,
+                };
+                libc::_exit(errno);
+            }
+
+            let mut status = 0;
+            assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+            assert!(libc::WIFEXITED(status), "child died with {:#x}", status);
+            let errno = libc::WEXITSTATUS(status);
+            assert_ne!(errno, 255, "failed to install filter");
+            errno
+        }
+    }
+
+    /// Returns whether `cmp` holds for the first argument of a system call, passed as `arg`.
+    ///
+    /// `getpid` ignores its arguments, but the filter still sees whatever is in their registers.
+    fn holds(cmp: Comparison, arg: u64) -> bool {
+        let mut policy = Policy::new(Action::Allow);
+        policy.rule(Rule::new(libc::SYS_getpid, Action::Errno(libc::EDOM)).arg(0, cmp));
+        errno_under(&policy, || unsafe { libc::syscall(libc::SYS_getpid, arg) }) == libc::EDOM
+    }
+
+    /// Checks `cmp` against arguments around `value`, which straddle the 32-bit halves.
+    fn check(cmp: Comparison, expected: &[(u64, bool)]) {
+        for &(arg, expected) in expected {
+            assert_eq!(holds(cmp, arg), expected, "{:?} against {:#x}", cmp, arg);
+        }
+    }
+
+    const VALUE: u64 = 0x1_0000_0005;
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn equal() {
+        check(
+            Comparison::Equal(VALUE),
+            &[
+                (VALUE, true),
+                (0x5, false),
+                (0x2_0000_0005, false),
+                (VALUE + 1, false),
+            ],
+        );
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn not_equal() {
+        check(
+            Comparison::NotEqual(VALUE),
+            &[
+                (VALUE, false),
+                (0x5, true),
+                (0x2_0000_0005, true),
+                (VALUE + 1, true),
+            ],
+        );
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn less() {
+        check(
+            Comparison::Less(VALUE),
+            &[
+                (VALUE - 1, true),
+                (VALUE, false),
+                (VALUE + 1, false),
+                (0xFFFF_FFFF, true),
+                (0x6, true),
+                (0x2_0000_0000, false),
+            ],
+        );
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn less_or_equal() {
+        check(
+            Comparison::LessOrEqual(VALUE),
+            &[
+                (VALUE - 1, true),
+                (VALUE, true),
+                (VALUE + 1, false),
+                (0x6, true),
+                (0x2_0000_0000, false),
+            ],
+        );
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn greater() {
+        check(
+            Comparison::Greater(VALUE),
+            &[
+                (VALUE - 1, false),
+                (VALUE, false),
+                (VALUE + 1, true),
+                (0x6, false),
+                (0x2_0000_0000, true),
+            ],
+        );
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn greater_or_equal() {
+        check(
+            Comparison::GreaterOrEqual(VALUE),
+            &[
+                (VALUE - 1, false),
+                (VALUE, true),
+                (VALUE + 1, true),
+                (0x6, false),
+                (0x2_0000_0000, true),
+            ],
+        );
+    }
+
+    #[test]
+    fn masked_ioctl() {
+        let policy = terminal_injection_policy();
+        let ioctl = |request: u64| unsafe { libc::syscall(libc::SYS_ioctl, -1, request, 0) };
+
+        assert_eq!(
+            errno_under(&policy, || ioctl(libc::TIOCSTI as _)),
+            libc::EPERM
+        );
+        assert_eq!(
+            errno_under(&policy, || ioctl(libc::TIOCLINUX as _)),
+            libc::EPERM
+        );
+        assert_eq!(
+            errno_under(&policy, || ioctl(libc::TIOCGWINSZ as _)),
+            libc::EBADF
+        );
+
+        // The kernel ignores the upper half of the request, so the filter must as well.
+        #[cfg(target_pointer_width = "64")]
+        {
+            let request = 0xDEAD_0000_0000 | u64::from(libc::TIOCSTI as u32);
+            assert_eq!(errno_under(&policy, || ioctl(request)), libc::EPERM);
+        }
+    }
+
+    #[test]
+    fn local_sockets() {
+        let policy = local_sockets_policy();
+        let socket = |domain: c_int| unsafe { libc::socket(domain, libc::SOCK_STREAM, 0) as _ };
+        let socketpair = |domain: c_int| unsafe {
+            let mut fds = [0; 2];
+            libc::socketpair(domain, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) as _
+        };
+
+        assert_eq!(errno_under(&policy, || socket(libc::AF_UNIX)), libc::EPERM);
+        assert_eq!(
+            errno_under(&policy, || socketpair(libc::AF_UNIX)),
+            libc::EPERM
+        );
+        assert_eq!(errno_under(&policy, || socket(libc::AF_INET)), 0);
+    }
+
+    #[test]
+    fn too_many_instructions() {
+        let mut policy = Policy::new(Action::Allow);
+        for _ in 0..BPF_MAXINSNS / 3 {
+            policy.deny(libc::SYS_getpid);
+        }
+
+        assert!(policy.compile().is_err());
+    }
+
+    #[test]
+    fn argument_index_out_of_range() {
+        let mut policy = Policy::new(Action::Allow);
+        policy.rule(Rule::new(libc::SYS_getpid, Action::Kill).arg(6, Comparison::Equal(0)));
+
+        assert!(policy.compile().is_err());
+    }
+}
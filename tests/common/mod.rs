@@ -0,0 +1,17 @@
+//! Helpers shared by the integration tests.
+
+use bastille::{Mapping, Sandbox};
+
+/// Returns a sandbox with a read-only `/usr` and the soft links of a merged `/usr` layout, which is
+/// enough to run `sh` and coreutils.
+pub fn sandbox() -> Sandbox {
+    let mut sandbox = Sandbox::new();
+    sandbox
+        .mount(Mapping::from_parts("/usr", "/usr", false).unwrap())
+        .soft_links(vec![
+            ("usr/bin", "/bin"),
+            ("usr/lib", "/lib"),
+            ("usr/lib64", "/lib64"),
+        ]);
+    sandbox
+}
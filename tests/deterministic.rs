@@ -0,0 +1,27 @@
+#![cfg(target_os = "linux")]
+
+use std::process::Command;
+
+use bastille::process::Stdio;
+
+mod common;
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn fixed_identity_and_environment() {
+    let output = common::sandbox()
+        .deterministic(true)
+        .stdout(Stdio::piped())
+        .spawn(Command::new("sh").args(["-c", "uname -n; id -u; umask; pwd; env"]))
+        .expect("Failed to spawn process in sandbox")
+        .wait_with_output()
+        .expect("Failed to wait on spawned process");
+    let output = String::from_utf8(output.stdout).unwrap();
+    let mut lines = output.lines();
+
+    assert_eq!(lines.next(), Some("localhost"));
+    assert_eq!(lines.next(), Some("1000"));
+    assert_eq!(lines.next(), Some("0022"));
+    assert_eq!(lines.next(), Some("/"));
+    assert!(lines.any(|line| line == "SOURCE_DATE_EPOCH=0"));
+}
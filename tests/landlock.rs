@@ -0,0 +1,120 @@
+#![cfg(target_os = "linux")]
+
+use std::net::{TcpListener, TcpStream};
+use std::process::{self, Command};
+use std::{env, fs, io};
+
+use bastille::landlock::Scope;
+use bastille::{Mapping, Namespaces};
+
+mod common;
+
+const CONNECTED: i32 = 0;
+const DENIED: i32 = 3;
+const FAILED: i32 = 4;
+
+/// Set in the environment of this test binary when it is re-executed inside the sandbox.
+const CONNECT_PORT: &str = "BASTILLE_TEST_CONNECT_PORT";
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn non_executable_mapping() {
+    let dir = env::temp_dir().join(format!("bastille-landlock-{}", process::id()));
+    fs::create_dir_all(&dir).expect("Failed to create scratch directory");
+
+    let scratch = Mapping::from_parts("/scratch", &dir, true)
+        .unwrap()
+        .executable(false);
+
+    // The scratch directory is writable, but nothing copied into it may be executed.
+    let status = common::sandbox()
+        .mount(scratch)
+        .landlock(true)
+        .spawn(Command::new("sh").args(["-c", "cp /usr/bin/true /scratch/true && ! /scratch/true"]))
+        .expect("Failed to spawn process in sandbox")
+        .wait()
+        .expect("Failed to wait on spawned process");
+
+    let copied = dir.join("true").exists();
+    fs::remove_dir_all(&dir).expect("Failed to remove scratch directory");
+    assert!(status.success());
+    assert!(copied);
+}
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn signals_scoped_to_sandbox() {
+    let mut host = Command::new("sleep")
+        .arg("60")
+        .spawn()
+        .expect("Failed to spawn host process");
+
+    // The sandbox shares the PID namespace of the host, so only the Landlock scope stands between
+    // it and the host process.
+    let mut child = common::sandbox()
+        .namespaces(Namespaces::new().pid(false))
+        .spawn(Command::new("sh").args(["-c", &format!("! kill -TERM {}", host.id())]))
+        .expect("Failed to spawn process in sandbox");
+    let scoped = child
+        .landlock()
+        .is_some_and(|status| status.scoped().contains(Scope::SIGNAL));
+    let status = child.wait().expect("Failed to wait on spawned process");
+
+    let alive = host
+        .try_wait()
+        .expect("Failed to poll host process")
+        .is_none();
+    host.kill().ok();
+    host.wait().ok();
+    if !scoped {
+        eprintln!("Landlock can't scope signals on this kernel, skipping");
+        return;
+    }
+    assert!(status.success());
+    assert!(alive);
+}
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn tcp_connect_ports() {
+    if let Ok(port) = env::var(CONNECT_PORT) {
+        process::exit(
+            match TcpStream::connect(("127.0.0.1", port.parse().unwrap())) {
+                Ok(_) => CONNECTED,
+                Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => DENIED,
+                Err(_) => FAILED,
+            },
+        );
+    }
+
+    let database = TcpListener::bind("127.0.0.1:0").expect("Failed to bind database port");
+    let other = TcpListener::bind("127.0.0.1:0").expect("Failed to bind other port");
+    let database = database.local_addr().unwrap().port();
+    let other = other.local_addr().unwrap().port();
+
+    assert_eq!(connect(database, database), CONNECTED);
+    assert_eq!(connect(database, other), DENIED);
+}
+
+/// Runs this test again inside a sandbox on the host network, which may only connect to `allowed`,
+/// returning how connecting to `port` went.
+fn connect(allowed: u16, port: u16) -> i32 {
+    let exe = env::current_exe().expect("Failed to find current executable");
+    let exe_dir = exe.parent().unwrap();
+
+    common::sandbox()
+        .mount(Mapping::from_parts(exe_dir, exe_dir, false).unwrap())
+        .allow_network(true)
+        .tcp_bind_ports(vec![])
+        .tcp_connect_ports(vec![allowed])
+        .spawn(
+            Command::new(&exe)
+                .args(["tcp_connect_ports", "--exact", "--ignored"])
+                .env(CONNECT_PORT, port.to_string()),
+        )
+        .expect("Failed to spawn process in sandbox")
+        .wait()
+        .expect("Failed to wait on spawned process")
+        .code()
+        .expect("Sandboxed process was killed")
+}
@@ -0,0 +1,63 @@
+#![cfg(target_os = "linux")]
+
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::process::{self, Command};
+use std::{env, fs, io};
+
+use bastille::Mapping;
+
+mod common;
+
+const CONNECTED: i32 = 0;
+const DENIED: i32 = 3;
+const FAILED: i32 = 4;
+
+/// Set in the environment of this test binary when it is re-executed inside the sandbox.
+const SOCKET_PATH: &str = "BASTILLE_TEST_SOCKET_PATH";
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn host_socket() {
+    if let Ok(path) = env::var(SOCKET_PATH) {
+        process::exit(match UnixStream::connect(path) {
+            Ok(_) => CONNECTED,
+            Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => DENIED,
+            Err(_) => FAILED,
+        });
+    }
+
+    let dir = env::temp_dir().join(format!("bastille-sockets-{}", process::id()));
+    fs::create_dir_all(&dir).expect("Failed to create socket directory");
+    let _listener = UnixListener::bind(dir.join("socket")).expect("Failed to bind host socket");
+
+    // The socket is reachable from inside the sandbox, so only the filter keeps it from connecting.
+    let allowed = connect(&dir, true);
+    let denied = connect(&dir, false);
+
+    fs::remove_dir_all(&dir).expect("Failed to remove socket directory");
+    assert_eq!(allowed, CONNECTED);
+    assert_eq!(denied, DENIED);
+}
+
+/// Runs this test again inside a sandbox with the socket directory mounted at `/sockets`, returning
+/// how connecting to the host socket went.
+fn connect(dir: &Path, allow_local_sockets: bool) -> i32 {
+    let exe = env::current_exe().expect("Failed to find current executable");
+    let exe_dir = exe.parent().unwrap();
+
+    common::sandbox()
+        .mount(Mapping::from_parts(exe_dir, exe_dir, false).unwrap())
+        .mount(Mapping::from_parts("/sockets", dir, true).unwrap())
+        .allow_local_sockets(allow_local_sockets)
+        .spawn(
+            Command::new(&exe)
+                .args(["host_socket", "--exact", "--ignored"])
+                .env(SOCKET_PATH, "/sockets/socket"),
+        )
+        .expect("Failed to spawn process in sandbox")
+        .wait()
+        .expect("Failed to wait on spawned process")
+        .code()
+        .expect("Sandboxed process was killed")
+}
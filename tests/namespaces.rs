@@ -0,0 +1,100 @@
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::process::Command;
+
+use bastille::process::Stdio;
+use bastille::{Mapping, Namespaces};
+
+mod common;
+
+const SHM_SIZE: u64 = 1024 * 1024;
+const UPTIME: i64 = 1000;
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn shares_pid_namespace_but_not_ipc() {
+    // Keep the PID view of the host, so `/proc` can be mapped in, but get a private IPC namespace.
+    let output = common::sandbox()
+        .mount(Mapping::from_parts("/proc", "/proc", false).unwrap())
+        .allow_sysctl(true)
+        .namespaces(Namespaces::new().pid(false).ipc(true))
+        .stdout(Stdio::piped())
+        .spawn(Command::new("readlink").args(["/proc/self/ns/pid", "/proc/self/ns/ipc"]))
+        .expect("Failed to spawn process in sandbox")
+        .wait_with_output()
+        .expect("Failed to wait on spawned process");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut links = stdout.lines();
+    let host_pid = fs::read_link("/proc/self/ns/pid").unwrap();
+    let host_ipc = fs::read_link("/proc/self/ns/ipc").unwrap();
+    assert_eq!(links.next(), host_pid.to_str());
+    assert_ne!(links.next(), host_ipc.to_str());
+}
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn hostname_and_domainname() {
+    // The UTS namespace is turned off explicitly, but setting a hostname turns it back on.
+    let output = common::sandbox()
+        .namespaces(Namespaces::new().uts(false))
+        .hostname("sandbox")
+        .domainname("example.invalid")
+        .stdout(Stdio::piped())
+        .spawn(Command::new("sh").args(["-c", "hostname; domainname"]))
+        .expect("Failed to spawn process in sandbox")
+        .wait_with_output()
+        .expect("Failed to wait on spawned process");
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"sandbox\nexample.invalid\n");
+}
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn private_shm_is_limited() {
+    // Filling the private `/dev/shm` beyond its size must fail, while `/dev/mqueue` is available.
+    let script = format!(
+        "yes | head -c {} > /dev/shm/small && ! (yes | head -c {} > /dev/shm/big) && test -d /dev/mqueue",
+        SHM_SIZE / 2,
+        SHM_SIZE * 2
+    );
+
+    let status = common::sandbox()
+        .shm_size(SHM_SIZE)
+        .spawn(Command::new("sh").args(["-c", &script]))
+        .expect("Failed to spawn process in sandbox")
+        .wait()
+        .expect("Failed to wait on spawned process");
+
+    assert!(status.success());
+}
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn boot_time_offset() {
+    let host_uptime = fs::read_to_string("/proc/uptime").unwrap();
+    let host_uptime: f64 = host_uptime
+        .split_whitespace()
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    // Shift the boot time clock so the sandbox appears to have been up for `UPTIME` seconds.
+    let output = common::sandbox()
+        .mount(Mapping::from_parts("/proc", "/proc", false).unwrap())
+        .allow_sysctl(true)
+        .time_offsets(0, UPTIME - host_uptime as i64)
+        .stdout(Stdio::piped())
+        .spawn(Command::new("cat").arg("/proc/uptime"))
+        .expect("Failed to spawn process in sandbox")
+        .wait_with_output()
+        .expect("Failed to wait on spawned process");
+
+    let uptime = String::from_utf8(output.stdout).unwrap();
+    let uptime: f64 = uptime.split_whitespace().next().unwrap().parse().unwrap();
+    assert!(uptime >= UPTIME as f64 && uptime < UPTIME as f64 + 10.0);
+}
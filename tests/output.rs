@@ -0,0 +1,121 @@
+#![cfg(target_os = "linux")]
+
+use std::env;
+use std::fs::{self, File};
+use std::process::{self, Command};
+
+use bastille::process::Stdio;
+use bastille::{Mapping, Namespaces};
+
+mod common;
+
+const LIMIT: usize = 4096;
+
+/// Writes far more than fits into a pipe buffer to stderr before touching stdout, which used to
+/// deadlock `wait_with_output()` since it read stdout to EOF first.
+fn command() -> Command {
+    let mut command = Command::new("sh");
+    command
+        .env_clear()
+        .env("PATH", "/usr/bin:/bin")
+        .args(["-c", "yes | head -c 1048576 >&2; echo done"]);
+    command
+}
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn output_is_drained_together() {
+    let output = common::sandbox()
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn(&mut command())
+        .expect("Failed to spawn process in sandbox")
+        .wait_with_output()
+        .expect("Failed to collect output");
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"done\n");
+    assert_eq!(output.stderr.len(), 1_048_576);
+}
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn output_is_capped() {
+    let output = common::sandbox()
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn(&mut command())
+        .expect("Failed to spawn process in sandbox")
+        .wait_with_output_capped(LIMIT)
+        .expect("Failed to collect output");
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"done\n");
+    assert!(!output.stdout_truncated);
+    assert_eq!(output.stderr.len(), LIMIT);
+    assert!(output.stderr_truncated);
+}
+
+/// Runs `script` with stderr merged into stdout, returning what ended up in the file `stdout` was
+/// connected to, either through the sandbox or only through the `Command` itself.
+fn merged_into_file(script: &str, on_command: bool) -> String {
+    let path = env::temp_dir().join(format!("bastille-merged-{}-{}", process::id(), on_command));
+    let file = File::create(&path).expect("Failed to create output file");
+
+    let mut sandbox = common::sandbox();
+    let mut command = Command::new("sh");
+    command.args(["-c", script]);
+    if on_command {
+        command.stdout(file);
+    } else {
+        sandbox.stdout(Stdio::from(file));
+    }
+
+    let status = sandbox
+        .stderr(Stdio::merged())
+        .spawn(&mut command)
+        .expect("Failed to spawn process in sandbox")
+        .wait()
+        .expect("Failed to wait on spawned process");
+
+    let output = fs::read_to_string(&path).expect("Failed to read output file");
+    fs::remove_file(&path).expect("Failed to remove output file");
+    assert!(status.success());
+    output
+}
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn merged_into_fd() {
+    assert_eq!(
+        merged_into_file("echo out; echo err >&2", false),
+        "out\nerr\n"
+    );
+}
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn merged_into_command_stdout() {
+    assert_eq!(
+        merged_into_file("echo out; echo err >&2", true),
+        "out\nerr\n"
+    );
+}
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn merged_into_null() {
+    // Keep the PID view of the host, so `/proc/self` can be mapped in to compare both streams.
+    let status = common::sandbox()
+        .mount(Mapping::from_parts("/proc", "/proc", false).unwrap())
+        .allow_sysctl(true)
+        .namespaces(Namespaces::new().pid(false))
+        .stdout(Stdio::null())
+        .stderr(Stdio::merged())
+        .spawn(Command::new("sh").args(["-c", "[ /proc/self/fd/1 -ef /proc/self/fd/2 ]"]))
+        .expect("Failed to spawn process in sandbox")
+        .wait()
+        .expect("Failed to wait on spawned process");
+
+    assert!(status.success());
+}
@@ -0,0 +1,90 @@
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use bastille::cgroup::Resources;
+use bastille::process::Stdio;
+use bastille::rlimit::Resource;
+
+mod common;
+
+// The cgroup tests need the parent of their own cgroup to be delegated, e.g. run them through
+// `systemd-run --user --scope cargo test -- --ignored`.
+
+#[test]
+#[ignore = "needs a delegated cgroup v2 subtree"]
+fn cgroup_limits() {
+    let resources = Resources::new()
+        .memory_max(256 * 1024 * 1024)
+        .cpu_max(Duration::from_millis(50), Duration::from_millis(100))
+        .pids_max(64);
+
+    let mut child = common::sandbox()
+        .resources(resources)
+        .spawn(Command::new("sleep").arg("1"))
+        .expect("Failed to spawn process in sandbox");
+
+    // The cgroup of the sandbox is created next to the one of this process.
+    let cgroup = cgroup_of(&child.id().to_string());
+    assert_eq!(cgroup.parent(), cgroup_of("self").parent());
+
+    assert_eq!(fs::read_to_string(cgroup.join("pids.max")).unwrap(), "64\n");
+    assert!(child.wait().expect("Failed to wait").success());
+    assert!(!cgroup.exists(), "cgroup should be removed once reaped");
+}
+
+#[test]
+#[ignore = "needs a delegated cgroup v2 subtree"]
+fn cgroup_namespace() {
+    let output = common::sandbox()
+        .resources(Resources::new().pids_max(64))
+        .cgroupfs(true)
+        .stdout(Stdio::piped())
+        .spawn(Command::new("cat").arg("/sys/fs/cgroup/pids.max"))
+        .expect("Failed to spawn process in sandbox")
+        .wait_with_output()
+        .expect("Failed to wait on spawned process");
+
+    // The root of the cgroup filesystem in the sandbox is the cgroup created for it.
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"64\n");
+}
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn rlimits() {
+    let mut sandbox = common::sandbox();
+    sandbox
+        .rlimit(Resource::Nofile, 64, 64)
+        .rlimit(Resource::Cpu, 1, 2);
+
+    let output = sandbox
+        .stdout(Stdio::piped())
+        .spawn(Command::new("sh").args(["-c", "ulimit -n"]))
+        .expect("Failed to spawn process in sandbox")
+        .wait_with_output()
+        .expect("Failed to wait on spawned process");
+    assert_eq!(output.stdout, b"64\n");
+
+    // Spinning past the soft CPU limit raises `SIGXCPU`, which is recognized through the init.
+    let mut child = sandbox
+        .stdout(Stdio::inherit())
+        .init(true)
+        .spawn(Command::new("sh").args(["-c", "while :; do :; done"]))
+        .expect("Failed to spawn process in sandbox");
+    child.wait().expect("Failed to wait on spawned process");
+    assert_eq!(child.exceeded_rlimit(), Some(Resource::Cpu));
+}
+
+/// Returns the path of the cgroup v2 of the process `pid` in the host hierarchy.
+fn cgroup_of(pid: &str) -> PathBuf {
+    let cgroups = fs::read_to_string(format!("/proc/{}/cgroup", pid)).unwrap();
+    let cgroup = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .expect("Process is not in a cgroup v2 hierarchy");
+    Path::new("/sys/fs/cgroup").join(cgroup.trim_start_matches('/'))
+}
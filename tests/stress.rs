@@ -0,0 +1,42 @@
+#![cfg(target_os = "linux")]
+
+use std::process::Command;
+use std::thread;
+
+mod common;
+
+const SANDBOXES: u32 = 64;
+
+#[test]
+#[ignore = "needs unprivileged user namespaces"]
+fn concurrent_sandboxes_keep_their_own_ids() {
+    let handles: Vec<_> = (0..SANDBOXES)
+        .map(|i| {
+            thread::spawn(move || {
+                let (uid, gid) = (1000 + i, 2000 + i);
+                let script = format!("test \"$(id -u)\" = {} && test \"$(id -g)\" = {}", uid, gid);
+
+                let status = common::sandbox()
+                    .uid(uid)
+                    .gid(gid)
+                    .allow_network(i % 2 == 0)
+                    .allow_sysctl(i % 3 == 0)
+                    .spawn(
+                        Command::new("sh")
+                            .env_clear()
+                            .env("PATH", "/usr/bin:/bin")
+                            .args(["-c", &script]),
+                    )
+                    .expect("Failed to spawn process in sandbox")
+                    .wait()
+                    .expect("Failed to wait on spawned process");
+
+                assert!(status.success(), "sandbox {} saw the wrong uid/gid", i);
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().expect("Sandbox thread panicked");
+    }
+}